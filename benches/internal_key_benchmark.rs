@@ -0,0 +1,78 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{BenchmarkId, Criterion};
+use nam_sparse_merkle_tree::InternalKey;
+use rand::{thread_rng, Rng};
+
+/// The key size of the IBC keys in `string_key`
+const IBC_KEY_LIMIT: usize = 300;
+
+/// The bit by bit fork height, as it was computed before the byte wise one
+fn naive_fork_height<const N: usize>(lhs: &InternalKey<N>, rhs: &InternalKey<N>) -> usize {
+    for h in (0..8 * N).rev() {
+        if lhs.get_bit(h) != rhs.get_bit(h) {
+            return h;
+        }
+    }
+    0
+}
+
+/// The bit by bit copy of a range of bits
+fn naive_copy_bits<const N: usize>(key: &InternalKey<N>, start: usize) -> InternalKey<N> {
+    let mut target = InternalKey::zero();
+    for i in start..8 * N {
+        if key.get_bit(i) {
+            target.set_bit(i);
+        }
+    }
+    target
+}
+
+/// Two random keys with a common prefix of N / 2 bytes, the fork is in
+/// the middle of the keys
+fn random_keys<const N: usize>(rng: &mut impl Rng) -> (InternalKey<N>, InternalKey<N>) {
+    let mut lhs = [0u8; N];
+    let mut rhs = [0u8; N];
+    rng.fill(&mut lhs[..]);
+    rng.fill(&mut rhs[..]);
+    let common = N / 2;
+    rhs[..common].copy_from_slice(&lhs[..common]);
+    (lhs.into(), rhs.into())
+}
+
+fn bench_key<const N: usize>(c: &mut Criterion) {
+    let mut rng = thread_rng();
+    let (lhs, rhs) = random_keys::<N>(&mut rng);
+
+    let mut group = c.benchmark_group(format!("InternalKey<{}> fork_height", N));
+    group.bench_function(BenchmarkId::from_parameter("naive"), |b| {
+        b.iter(|| naive_fork_height(&lhs, &rhs))
+    });
+    group.bench_function(BenchmarkId::from_parameter("bytes"), |b| {
+        b.iter(|| lhs.fork_height(&rhs))
+    });
+    group.finish();
+
+    let height = lhs.fork_height(&rhs);
+    let mut group = c.benchmark_group(format!("InternalKey<{}> copy_bits", N));
+    group.bench_function(BenchmarkId::from_parameter("naive"), |b| {
+        b.iter(|| naive_copy_bits(&lhs, height))
+    });
+    group.bench_function(BenchmarkId::from_parameter("bytes"), |b| {
+        b.iter(|| lhs.copy_bits(height..))
+    });
+    group.finish();
+}
+
+fn bench_internal_keys(c: &mut Criterion) {
+    bench_key::<32>(c);
+    bench_key::<IBC_KEY_LIMIT>(c);
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_internal_keys
+);
+criterion_main!(benches);
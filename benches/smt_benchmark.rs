@@ -3,9 +3,9 @@ extern crate criterion;
 
 mod string_key;
 
-use criterion::Criterion;
+use criterion::{BenchmarkId, Criterion};
 use rand::{thread_rng, Rng};
-use sparse_merkle_tree::{
+use nam_sparse_merkle_tree::{
     sha256::Sha256Hasher, default_store::DefaultStore,
     tree::SparseMerkleTree, H256, Hash
 };
@@ -48,29 +48,29 @@ fn random_stringsmt(update_count: usize, rng: &mut impl Rng) -> (StringSmt, Vec<
 }
 
 fn bench_hashes(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "ShaSmt update",
-        |b, &&size| {
+    let mut group = c.benchmark_group("ShaSmt update");
+    for size in [100, 10_000].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             b.iter(|| {
                 let mut rng = thread_rng();
                 random_shasmt(size, &mut rng)
             });
-        },
-        &[100, 10_000],
-    );
+        });
+    }
+    group.finish();
 
-    c.bench_function_over_inputs(
-        "ShaSmt get",
-        |b, &&size| {
+    let mut group = c.benchmark_group("ShaSmt get");
+    for size in [5_000, 10_000].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             let mut rng = thread_rng();
             let (smt, _keys) = random_shasmt(size, &mut rng);
             b.iter(|| {
                 let key = random_h256(&mut rng).into();
                 smt.get(&key).unwrap();
             });
-        },
-        &[5_000, 10_000],
-    );
+        });
+    }
+    group.finish();
 
     c.bench_function("ShaSmt generate merkle proof", |b| {
         let mut rng = thread_rng();
@@ -109,29 +109,29 @@ fn bench_hashes(c: &mut Criterion) {
 }
 
 fn bench_strings(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "StringSmt update",
-        |b, &&size| {
+    let mut group = c.benchmark_group("StringSmt update");
+    for size in [100, 10_000].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             b.iter(|| {
                 let mut rng = thread_rng();
                 random_stringsmt(size, &mut rng)
             });
-        },
-        &[100, 10_000],
-    );
+        });
+    }
+    group.finish();
 
-    c.bench_function_over_inputs(
-        "StringSmt get",
-        |b, &&size| {
+    let mut group = c.benchmark_group("StringSmt get");
+    for size in [5_000, 10_000].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             let mut rng = thread_rng();
             let (smt, _keys) = random_stringsmt(size, &mut rng);
             b.iter(|| {
-                let key = random_stringkey(&mut rng).into();
+                let key = random_stringkey(&mut rng);
                 smt.get(&key).unwrap();
             });
-        },
-        &[5_000, 10_000],
-    );
+        });
+    }
+    group.finish();
 
     c.bench_function("StringSmt generate merkle proof", |b| {
         let mut rng = thread_rng();
@@ -173,6 +173,6 @@ fn bench_strings(c: &mut Criterion) {
 criterion_group!(
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = bench_strings
+    targets = bench_hashes, bench_strings
 );
 criterion_main!(benches);
@@ -5,6 +5,10 @@ const BLAKE2B_KEY: &[u8] = &[];
 const BLAKE2B_LEN: usize = 32;
 const PERSONALIZATION: &[u8] = b"sparsemerkletree";
 
+/// Blake2b hasher personalized with "sparsemerkletree"
+///
+/// ICS23 specs can't express the personalization, see `blake2s::Blake2sHasher`
+/// for trees whose proofs are verified with ICS23
 pub struct Blake2bHasher(Blake2b);
 
 impl Default for Blake2bHasher {
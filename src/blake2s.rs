@@ -0,0 +1,21 @@
+use crate::{traits::Hasher, H256};
+use blake2::{Blake2s256, Digest};
+
+/// Unpersonalized Blake2s256 hasher, which ICS23 specs can express
+#[derive(Default)]
+pub struct Blake2sHasher(Blake2s256);
+
+impl Hasher for Blake2sHasher {
+    fn write_bytes(&mut self, h: &[u8]) {
+        self.0.update(h);
+    }
+
+    fn finish(self) -> H256 {
+        let hash: [u8; 32] = self.0.finalize().into();
+        hash.into()
+    }
+
+    fn hash_op() -> ics23::HashOp {
+        ics23::HashOp::Blake2s256
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    default_store::{Entry, Map},
+    error::{Error, Result},
+    traits::{BlobStore, Hasher, Store},
+    tree::SparseMerkleTree,
+    vec::Vec,
+    Key, MerkleProof, H256,
+};
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// The digest a leaf of `BlobTree` commits to, zero for the empty blob
+pub fn blob_digest<H: Hasher + Default>(blob: &[u8]) -> H256 {
+    if blob.is_empty() {
+        return H256::zero();
+    }
+    let mut hasher = H::default();
+    hasher.write_bytes(blob);
+    hasher.finish()
+}
+
+/// In memory blob store counting the leaves which commit to each blob
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+pub struct DefaultBlobStore {
+    blobs_map: Map<H256, (usize, Vec<u8>)>,
+}
+
+impl DefaultBlobStore {
+    /// return the blobs with the number of leaves committing to them
+    pub fn blobs_map(&self) -> &Map<H256, (usize, Vec<u8>)> {
+        &self.blobs_map
+    }
+    pub fn clear(&mut self) {
+        self.blobs_map.clear();
+    }
+}
+
+impl BlobStore for DefaultBlobStore {
+    fn get_blob(&self, digest: &H256) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .blobs_map
+            .get(digest)
+            .map(|(_count, blob)| blob.clone()))
+    }
+    fn insert_blob(&mut self, digest: H256, blob: Vec<u8>) -> Result<()> {
+        self.blobs_map.entry(digest).or_insert((0, blob)).0 += 1;
+        Ok(())
+    }
+    fn remove_blob(&mut self, digest: &H256) -> Result<()> {
+        if let Entry::Occupied(mut entry) = self.blobs_map.entry(*digest) {
+            entry.get_mut().0 -= 1;
+            if entry.get().0 == 0 {
+                entry.remove();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sparse merkle tree whose leaves hold the digests of the values
+///
+/// The leaves commit to `blob_digest` of the values, which are kept in a
+/// `BlobStore` instead of the tree store. The inner tree is a plain tree of
+/// digests, its merkle proofs are verified with the digests as leaf values.
+#[derive(Debug)]
+pub struct BlobTree<H, K, S, B, const N: usize>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    S: Store<K, H256, N>,
+    B: BlobStore,
+{
+    tree: SparseMerkleTree<H, K, H256, S, N>,
+    blobs: B,
+}
+
+impl<H, K, S, B, const N: usize> Default for BlobTree<H, K, S, B, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    S: Store<K, H256, N>,
+    B: BlobStore,
+{
+    fn default() -> Self {
+        Self::new(SparseMerkleTree::default(), B::default())
+    }
+}
+
+impl<H, K, S, B, const N: usize> BlobTree<H, K, S, B, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    S: Store<K, H256, N>,
+    B: BlobStore,
+{
+    /// Build a blob tree from a tree of digests and the store of their blobs
+    pub fn new(tree: SparseMerkleTree<H, K, H256, S, N>, blobs: B) -> Self {
+        BlobTree { tree, blobs }
+    }
+
+    pub fn root(&self) -> &H256 {
+        self.tree.root()
+    }
+
+    /// return the inner tree of digests
+    pub fn tree(&self) -> &SparseMerkleTree<H, K, H256, S, N> {
+        &self.tree
+    }
+
+    pub fn blobs(&self) -> &B {
+        &self.blobs
+    }
+
+    pub fn take_stores(self) -> (S, B) {
+        (self.tree.take_store(), self.blobs)
+    }
+
+    /// Update a leaf, return new merkle root
+    /// set to the empty blob to delete a key
+    ///
+    /// The new blob is stored before the tree commits to it and the old one
+    /// removed after, so the leaves never commit to a missing blob.
+    pub fn update(&mut self, key: K, blob: Vec<u8>) -> Result<&H256> {
+        let digest = blob_digest::<H>(&blob);
+        let old_digest = self.tree.get(&key)?;
+        if digest == old_digest {
+            return Ok(self.tree.root());
+        }
+        if !digest.is_zero() {
+            self.blobs.insert_blob(digest, blob)?;
+        }
+        if let Err(err) = self.tree.update(key, digest) {
+            if !digest.is_zero() {
+                self.blobs.remove_blob(&digest)?;
+            }
+            return Err(err);
+        }
+        if !old_digest.is_zero() {
+            self.blobs.remove_blob(&old_digest)?;
+        }
+        Ok(self.tree.root())
+    }
+
+    /// Get the digest of a leaf
+    /// return zero if leaf not exists
+    pub fn get_digest(&self, key: &K) -> Result<H256> {
+        self.tree.get(key)
+    }
+
+    /// Get the blob of a leaf
+    /// return the empty blob if leaf not exists, and MissingBlob error if
+    /// the blob store doesn't have the blob of the leaf
+    pub fn get(&self, key: &K) -> Result<Vec<u8>> {
+        let digest = self.tree.get(key)?;
+        if digest.is_zero() {
+            return Ok(Vec::new());
+        }
+        self.blobs
+            .get_blob(&digest)?
+            .ok_or(Error::MissingBlob(digest))
+    }
+
+    /// Generate merkle proof of the digests of the leaves
+    pub fn merkle_proof(&self, keys: Vec<K>) -> Result<MerkleProof> {
+        self.tree.merkle_proof(keys)
+    }
+}
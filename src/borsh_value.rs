@@ -0,0 +1,96 @@
+use crate::{traits::Value, vec::Vec};
+use borsh::{
+    io::{Read, Result, Write},
+    BorshDeserialize, BorshSerialize,
+};
+
+/// Value adapter for any borsh type, the tree commits to the hash of the
+/// borsh encoding, see `Value::is_prehashed`
+///
+/// The zero value, which deletes a leaf, is the one without an encoding:
+/// `BorshValue::zero()`, or a value whose encoding is empty like `()`. Any
+/// other value is stored, even the default of its type.
+#[derive(Debug, Clone)]
+pub struct BorshValue<T> {
+    value: Option<T>,
+    encoding: Vec<u8>,
+}
+
+impl<T: BorshSerialize> BorshValue<T> {
+    /// return the errors of the borsh serialization of the value
+    pub fn new(value: T) -> Result<Self> {
+        let encoding = borsh::to_vec(&value)?;
+        let value = if encoding.is_empty() {
+            None
+        } else {
+            Some(value)
+        };
+        Ok(BorshValue { value, encoding })
+    }
+}
+
+impl<T> BorshValue<T> {
+    /// return None for the zero value
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// return None for the zero value
+    pub fn into_inner(self) -> Option<T> {
+        self.value
+    }
+
+    /// The borsh encoding of the value, empty for the zero value
+    pub fn encoding(&self) -> &[u8] {
+        &self.encoding
+    }
+}
+
+/// Values are equal if their encodings are equal
+impl<T> PartialEq for BorshValue<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.encoding == other.encoding
+    }
+}
+
+impl<T> Eq for BorshValue<T> {}
+
+impl<T: Clone> Value for BorshValue<T> {
+    fn as_slice(&self) -> &[u8] {
+        &self.encoding
+    }
+
+    fn zero() -> Self {
+        BorshValue {
+            value: None,
+            encoding: Vec::new(),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.encoding.is_empty()
+    }
+
+    fn is_prehashed() -> bool {
+        true
+    }
+}
+
+/// Encoded as the bytes of the encoding of the value
+impl<T> BorshSerialize for BorshValue<T> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        BorshSerialize::serialize(&self.encoding, writer)
+    }
+}
+
+impl<T: BorshDeserialize> BorshDeserialize for BorshValue<T> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let encoding: Vec<u8> = BorshDeserialize::deserialize_reader(reader)?;
+        let value = if encoding.is_empty() {
+            None
+        } else {
+            Some(T::try_from_slice(&encoding)?)
+        };
+        Ok(BorshValue { value, encoding })
+    }
+}
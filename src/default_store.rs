@@ -1,7 +1,13 @@
-use std::ops::Deref;
-use crate::{collections, error::Error, traits::Store, tree::{BranchNode, LeafNode}, Key, H256};
+use crate::{
+    collections,
+    error::Error,
+    traits::{IterableStore, Store},
+    tree::{BranchNode, LeafNode},
+    Key, H256,
+};
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
+use core::ops::Deref;
 use itertools::Itertools;
 
 #[derive(Debug, Clone)]
@@ -47,10 +53,10 @@ where
     K: Key<N>,
 {
     fn get_branch(&self, node: &H256) -> Result<Option<BranchNode<K, N>>, Error> {
-        Ok(self.branches_map.get(node).map(Clone::clone))
+        Ok(self.branches_map.get(node).cloned())
     }
     fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<K, V, N>>, Error> {
-        Ok(self.leaves_map.get(leaf_hash).map(Clone::clone))
+        Ok(self.leaves_map.get(leaf_hash).cloned())
     }
     fn insert_branch(&mut self, node: H256, branch: BranchNode<K, N>) -> Result<(), Error> {
         self.branches_map.insert(node, branch);
@@ -81,6 +87,18 @@ where
     }
 }
 
+impl<K, V: Clone, const N: usize> IterableStore<K, V, N> for DefaultStore<K, V, N>
+where
+    K: Key<N>,
+{
+    fn branch_hashes(&self) -> impl Iterator<Item = H256> + '_ {
+        self.branches_map.keys().copied()
+    }
+    fn leaf_hashes(&self) -> impl Iterator<Item = H256> + '_ {
+        self.leaves_map.keys().copied()
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "std")] {
         pub type Map<K, V> = collections::HashMap<K, V>;
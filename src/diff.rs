@@ -0,0 +1,219 @@
+use crate::{
+    error::Result,
+    traits::{Store, Value},
+    tree::BranchNode,
+    vec::Vec,
+    InternalKey, Key, H256,
+};
+use core::{cmp::Ordering, marker::PhantomData};
+
+/// A change of a leaf between two roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    Added(K, V),
+    Removed(K, V),
+    Modified { key: K, old: V, new: V },
+}
+
+impl<K, V> Change<K, V> {
+    /// return the key of the changed leaf
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Added(key, _) | Change::Removed(key, _) => key,
+            Change::Modified { key, .. } => key,
+        }
+    }
+}
+
+/// Iterator over the changes from an old root to a new one in ascending key
+/// order
+///
+/// Both trees are walked from their roots side by side, subtrees with equal
+/// hashes are skipped without being loaded from the store.
+pub struct Diff<'a, K, V, S, const N: usize>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    store: &'a S,
+    // pairs of old and new subtrees to compare, the next one on the top
+    stack: Vec<(Option<H256>, Option<H256>)>,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, S, const N: usize> Diff<'a, K, V, S, N>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    /// Compare the trees with the roots, which nodes must be in the store
+    pub fn new(store: &'a S, old_root: &H256, new_root: &H256) -> Self {
+        let mut diff = Diff {
+            store,
+            stack: Vec::new(),
+            phantom: PhantomData,
+        };
+        diff.push(Some(*old_root), Some(*new_root));
+        diff
+    }
+
+    fn push(&mut self, old: Option<H256>, new: Option<H256>) {
+        let old = old.filter(|node| !node.is_zero());
+        let new = new.filter(|node| !node.is_zero());
+        if old != new {
+            self.stack.push((old, new));
+        }
+    }
+
+    /// Push the pairs of the subtrees, the left one is visited first
+    fn push_pairs(
+        &mut self,
+        left: (Option<H256>, Option<H256>),
+        right: (Option<H256>, Option<H256>),
+    ) {
+        self.push(right.0, right.1);
+        self.push(left.0, left.1);
+    }
+
+    fn next_change(&mut self) -> Result<Option<Change<K, V>>> {
+        while let Some(pair) = self.stack.pop() {
+            let (old, new) = match pair {
+                (Some(old), Some(new)) => (old, new),
+                (Some(old), None) => match self.expand(&old, true)? {
+                    Some((key, value)) => return Ok(Some(Change::Removed(key, value))),
+                    None => continue,
+                },
+                (None, Some(new)) => match self.expand(&new, false)? {
+                    Some((key, value)) => return Ok(Some(Change::Added(key, value))),
+                    None => continue,
+                },
+                (None, None) => continue,
+            };
+            let (old_branch, new_branch) =
+                match (self.store.get_branch(&old)?, self.store.get_branch(&new)?) {
+                    (Some(old_branch), Some(new_branch)) => (old_branch, new_branch),
+                    (Some(_), None) => {
+                        self.push(Some(old), None);
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        self.push(None, Some(new));
+                        continue;
+                    }
+                    (None, None) => continue,
+                };
+            let old_height = height(&old_branch);
+            let new_height = height(&new_branch);
+            let old_prefix = prefix(&old_branch.key, old_height.max(new_height));
+            let new_prefix = prefix(&new_branch.key, old_height.max(new_height));
+            match old_prefix.cmp(&new_prefix) {
+                // the subtrees are disjoint
+                Ordering::Less => self.push_pairs((Some(old), None), (None, Some(new))),
+                Ordering::Greater => self.push_pairs((None, Some(new)), (Some(old), None)),
+                Ordering::Equal if old_height == new_height => {
+                    if old_branch.is_leaf() {
+                        // the same key with another value
+                        if let Some(change) = self.modified(&old, &new)? {
+                            return Ok(Some(change));
+                        }
+                        continue;
+                    }
+                    let (old_left, old_right) = old_branch.branch(old_branch.fork_height);
+                    let (new_left, new_right) = new_branch.branch(new_branch.fork_height);
+                    self.push_pairs(
+                        (Some(*old_left), Some(*new_left)),
+                        (Some(*old_right), Some(*new_right)),
+                    );
+                }
+                // the new subtree is on one side of the old one
+                Ordering::Equal if old_height > new_height => {
+                    let (left, right) = old_branch.branch(old_branch.fork_height);
+                    if new_branch.key.get_bit(old_branch.fork_height) {
+                        self.push_pairs((Some(*left), None), (Some(*right), Some(new)));
+                    } else {
+                        self.push_pairs((Some(*left), Some(new)), (Some(*right), None));
+                    }
+                }
+                // the old subtree is on one side of the new one
+                Ordering::Equal => {
+                    let (left, right) = new_branch.branch(new_branch.fork_height);
+                    if old_branch.key.get_bit(new_branch.fork_height) {
+                        self.push_pairs((None, Some(*left)), (Some(old), Some(*right)));
+                    } else {
+                        self.push_pairs((Some(old), Some(*left)), (None, Some(*right)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// return the leaf of a subtree of one of the trees, or push its children
+    fn expand(&mut self, node: &H256, is_old: bool) -> Result<Option<(K, V)>> {
+        let branch = match self.store.get_branch(node)? {
+            Some(branch) => branch,
+            None => return Ok(None),
+        };
+        if branch.is_leaf() {
+            return Ok(self
+                .store
+                .get_leaf(node)?
+                .map(|leaf| (leaf.key, leaf.value)));
+        }
+        let (left, right) = branch.branch(branch.fork_height);
+        if is_old {
+            self.push_pairs((Some(*left), None), (Some(*right), None));
+        } else {
+            self.push_pairs((None, Some(*left)), (None, Some(*right)));
+        }
+        Ok(None)
+    }
+
+    fn modified(&self, old: &H256, new: &H256) -> Result<Option<Change<K, V>>> {
+        match (self.store.get_leaf(old)?, self.store.get_leaf(new)?) {
+            (Some(old), Some(new)) => Ok(Some(Change::Modified {
+                key: new.key,
+                old: old.value,
+                new: new.value,
+            })),
+            (Some(old), None) => Ok(Some(Change::Removed(old.key, old.value))),
+            (None, Some(new)) => Ok(Some(Change::Added(new.key, new.value))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Height above which the keys of the subtree share their bits, 0 for a leaf
+fn height<K: Key<N>, const N: usize>(branch: &BranchNode<K, N>) -> usize {
+    if branch.is_leaf() {
+        0
+    } else {
+        branch.fork_height + 1
+    }
+}
+
+fn prefix<const N: usize>(key: &InternalKey<N>, height: usize) -> InternalKey<N> {
+    key.copy_bits(height..)
+}
+
+impl<'a, K, V, S, const N: usize> Iterator for Diff<'a, K, V, S, N>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    type Item = Result<Change<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_change() {
+            Ok(change) => change.map(Ok),
+            Err(err) => {
+                // stop the iteration after a store error
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -17,6 +17,12 @@ pub enum Error {
     ExistenceProof,
     NonExistenceProof,
     KeyTooLarge,
+    UnsortedKeys,
+    InvalidChunk(u64),
+    IncompleteSnapshot,
+    StackOverflow(usize),
+    MissingBlob(H256),
+    UnsupportedPrehash,
 }
 
 impl core::fmt::Display for Error {
@@ -65,6 +71,28 @@ impl core::fmt::Display for Error {
             Error::KeyTooLarge => {
                 write!(f, "Provided key has too many bytes")?;
             }
+            Error::UnsortedKeys => {
+                write!(f, "Keys are not sorted in strictly ascending order")?;
+            }
+            Error::InvalidChunk(index) => {
+                write!(f, "Invalid snapshot chunk {}", index)?;
+            }
+            Error::IncompleteSnapshot => {
+                write!(f, "Snapshot is incomplete or doesn't match the root")?;
+            }
+            Error::StackOverflow(capacity) => {
+                write!(
+                    f,
+                    "Compiled proof needs more than {} stack entries",
+                    capacity
+                )?;
+            }
+            Error::MissingBlob(digest) => {
+                write!(f, "Missing blob of digest {:?}", digest)?;
+            }
+            Error::UnsupportedPrehash => {
+                write!(f, "Keys are prehashed without an ICS23 hash operation")?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,375 @@
+use crate::{
+    default_store::Map,
+    error::{Error, Result},
+    string::ToString,
+    traits::{IterableStore, Store},
+    tree::{BranchNode, LeafNode},
+    vec::Vec,
+    Key, H256,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// size of the batch length and checksums around the payload
+const LENGTH_SIZE: usize = 8;
+const CHECKSUM_SIZE: usize = 32;
+const HEADER_SIZE: usize = LENGTH_SIZE + CHECKSUM_SIZE;
+
+/// A change of the store, appended to the log
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+enum Record<K, V, const N: usize>
+where
+    K: Key<N>,
+{
+    InsertBranch(H256, BranchNode<K, N>),
+    InsertLeaf(H256, LeafNode<K, V, N>),
+    RemoveBranch(H256),
+    RemoveLeaf(H256),
+}
+
+/// Store persisted in an append-only log file
+///
+/// Each commit appends the changes since the previous one to the log as a
+/// single batch: a header of its length and the sha256 checksum of the length,
+/// the borsh encoding of the root and the changes, and the sha256 checksum of
+/// the encoding. The batch is flushed to the disk before the commit returns.
+/// Opening the log replays the batches and drops the last one if it was torn
+/// by a crash, so the store resumes at the last commit. A corrupted header or
+/// a corrupted batch before the last one is an error.
+///
+/// The nodes are indexed in memory. A default store has no log file.
+#[derive(Debug)]
+pub struct FileStore<K, V, const N: usize>
+where
+    K: Key<N>,
+{
+    branches_map: Map<H256, BranchNode<K, N>>,
+    leaves_map: Map<H256, LeafNode<K, V, N>>,
+    // changes since the last commit
+    pending: Vec<Record<K, V, N>>,
+    root: H256,
+    log: Option<Log>,
+}
+
+#[derive(Debug)]
+struct Log {
+    path: PathBuf,
+    file: File,
+    // length of the committed batches
+    len: u64,
+}
+
+impl<K, V, const N: usize> Default for FileStore<K, V, N>
+where
+    K: Key<N>,
+{
+    fn default() -> Self {
+        Self {
+            branches_map: Map::new(),
+            leaves_map: Map::new(),
+            pending: Vec::new(),
+            root: H256::zero(),
+            log: None,
+        }
+    }
+}
+
+impl<K, V, const N: usize> FileStore<K, V, N>
+where
+    K: Key<N> + BorshSerialize + BorshDeserialize,
+    V: Clone + BorshSerialize + BorshDeserialize,
+{
+    /// Open the store logged in the file, which is created if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(store_error)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(store_error)?;
+
+        if bytes.is_empty() {
+            // the log may have been created
+            sync_dir(&path)?;
+        }
+
+        let mut store = Self::default();
+        let mut offset = 0;
+        while let Some((root, records, len)) = decode_batch(&bytes, offset)? {
+            store.replay(records);
+            store.root = root;
+            offset += len;
+        }
+        if offset < bytes.len() {
+            // drop the last batch torn by a crash
+            file.set_len(offset as u64).map_err(store_error)?;
+            file.sync_all().map_err(store_error)?;
+        }
+        store.log = Some(Log {
+            path,
+            file,
+            len: offset as u64,
+        });
+        Ok(store)
+    }
+
+    /// Append the changes since the last commit to the log with the root of
+    /// the tree they lead to
+    pub fn commit(&mut self, root: &H256) -> Result<()> {
+        if let Some(log) = self.log.as_mut() {
+            let batch = encode_batch(root, &self.pending)?;
+            let written = log
+                .file
+                .write_all(&batch)
+                .and_then(|_| log.file.sync_data());
+            if let Err(err) = written {
+                // don't leave a partial batch before the next commits
+                let _ = log.file.set_len(log.len);
+                return Err(store_error(err));
+            }
+            log.len += batch.len() as u64;
+        }
+        self.pending.clear();
+        self.root = *root;
+        Ok(())
+    }
+
+    /// Rewrite the log as a single batch of the committed nodes
+    ///
+    /// The new log is written to a temporary file which then replaces the log,
+    /// the rename is flushed to the disk with the directory of the log
+    pub fn compact(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            return Err(Error::Store(
+                "Can't compact uncommitted changes".to_string(),
+            ));
+        }
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        let records: Vec<Record<K, V, N>> = self
+            .branches_map
+            .iter()
+            .map(|(node, branch)| Record::InsertBranch(*node, branch.clone()))
+            .chain(
+                self.leaves_map
+                    .iter()
+                    .map(|(leaf_hash, leaf)| Record::InsertLeaf(*leaf_hash, leaf.clone())),
+            )
+            .collect();
+        let batch = encode_batch(&self.root, &records)?;
+        let compact_path = log.path.with_extension("compact");
+        let mut compact_file = File::create(&compact_path).map_err(store_error)?;
+        compact_file.write_all(&batch).map_err(store_error)?;
+        compact_file.sync_all().map_err(store_error)?;
+        fs::rename(&compact_path, &log.path).map_err(store_error)?;
+        sync_dir(&log.path)?;
+        log.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&log.path)
+            .map_err(store_error)?;
+        log.len = batch.len() as u64;
+        Ok(())
+    }
+
+    fn replay(&mut self, records: Vec<Record<K, V, N>>) {
+        for record in records {
+            match record {
+                Record::InsertBranch(node, branch) => {
+                    self.branches_map.insert(node, branch);
+                }
+                Record::InsertLeaf(leaf_hash, leaf) => {
+                    self.leaves_map.insert(leaf_hash, leaf);
+                }
+                Record::RemoveBranch(node) => {
+                    self.branches_map.remove(&node);
+                }
+                Record::RemoveLeaf(leaf_hash) => {
+                    self.leaves_map.remove(&leaf_hash);
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, const N: usize> FileStore<K, V, N>
+where
+    K: Key<N>,
+{
+    /// return the root of the last commit, to resume the tree
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    /// Check if there are changes since the last commit
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn branches_map(&self) -> &Map<H256, BranchNode<K, N>> {
+        &self.branches_map
+    }
+
+    pub fn leaves_map(&self) -> &Map<H256, LeafNode<K, V, N>> {
+        &self.leaves_map
+    }
+}
+
+impl<K, V: Clone, const N: usize> Store<K, V, N> for FileStore<K, V, N>
+where
+    K: Key<N>,
+{
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode<K, N>>> {
+        Ok(self.branches_map.get(node).cloned())
+    }
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<K, V, N>>> {
+        Ok(self.leaves_map.get(leaf_hash).cloned())
+    }
+    fn insert_branch(&mut self, node: H256, branch: BranchNode<K, N>) -> Result<()> {
+        self.pending
+            .push(Record::InsertBranch(node, branch.clone()));
+        self.branches_map.insert(node, branch);
+        Ok(())
+    }
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<K, V, N>) -> Result<()> {
+        self.pending
+            .push(Record::InsertLeaf(leaf_hash, leaf.clone()));
+        self.leaves_map.insert(leaf_hash, leaf);
+        Ok(())
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<()> {
+        if self.branches_map.remove(node).is_some() {
+            self.pending.push(Record::RemoveBranch(*node));
+        }
+        Ok(())
+    }
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<()> {
+        if self.leaves_map.remove(leaf_hash).is_some() {
+            self.pending.push(Record::RemoveLeaf(*leaf_hash));
+        }
+        Ok(())
+    }
+
+    fn sorted_leaves<'a>(&'a self) -> impl Iterator<Item = (K, &'a V)>
+    where
+        V: 'a,
+    {
+        self.leaves_map
+            .iter()
+            .sorted_by_key(|(_, v)| <K as Deref>::deref(&v.key))
+            .map(|(_, v)| (v.key, &v.value))
+    }
+
+    fn size(&self) -> usize {
+        self.leaves_map.len()
+    }
+}
+
+impl<K, V: Clone, const N: usize> IterableStore<K, V, N> for FileStore<K, V, N>
+where
+    K: Key<N>,
+{
+    fn branch_hashes(&self) -> impl Iterator<Item = H256> + '_ {
+        self.branches_map.keys().copied()
+    }
+    fn leaf_hashes(&self) -> impl Iterator<Item = H256> + '_ {
+        self.leaves_map.keys().copied()
+    }
+}
+
+fn store_error<E: ToString>(err: E) -> Error {
+    Error::Store(err.to_string())
+}
+
+/// Flush the entries of the directory of the file to the disk, so that its
+/// creation or renaming survives a crash
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(store_error)
+}
+
+/// Directories can't be flushed on this platform
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// length | length checksum | payload | payload checksum
+fn encode_batch<K, V, const N: usize>(root: &H256, records: &[Record<K, V, N>]) -> Result<Vec<u8>>
+where
+    K: Key<N> + BorshSerialize,
+    V: BorshSerialize,
+{
+    let payload = borsh::to_vec(&(root, records)).map_err(store_error)?;
+    let mut batch = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+    let length = (payload.len() as u64).to_le_bytes();
+    batch.extend_from_slice(&length);
+    batch.extend_from_slice(&Sha256::digest(length));
+    batch.extend_from_slice(&payload);
+    batch.extend_from_slice(&Sha256::digest(&payload));
+    Ok(batch)
+}
+
+/// Decode the batch at offset of the log and its length, None if the batch
+/// is the last one and is incomplete or its payload checksum doesn't match,
+/// as a batch torn by a crash
+///
+/// return Store error if the checksum of the header doesn't match, or the
+/// payload checksum of a batch followed by others
+#[allow(clippy::type_complexity)]
+fn decode_batch<K, V, const N: usize>(
+    log: &[u8],
+    offset: usize,
+) -> Result<Option<(H256, Vec<Record<K, V, N>>, usize)>>
+where
+    K: Key<N> + BorshDeserialize,
+    V: BorshDeserialize,
+{
+    let bytes = &log[offset..];
+    if bytes.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut length = [0u8; LENGTH_SIZE];
+    length.copy_from_slice(&bytes[..LENGTH_SIZE]);
+    // a wrong length would make the next batches look torn
+    if Sha256::digest(length).as_slice() != &bytes[LENGTH_SIZE..HEADER_SIZE] {
+        return Err(Error::Store(format!(
+            "Corrupted batch header at offset {}",
+            offset
+        )));
+    }
+    let payload_len = u64::from_le_bytes(length) as usize;
+    let batch_len = match payload_len.checked_add(HEADER_SIZE + CHECKSUM_SIZE) {
+        Some(batch_len) if batch_len <= bytes.len() => batch_len,
+        _ => return Ok(None),
+    };
+    let payload = &bytes[HEADER_SIZE..HEADER_SIZE + payload_len];
+    let checksum = &bytes[HEADER_SIZE + payload_len..batch_len];
+    if Sha256::digest(payload).as_slice() != checksum {
+        if batch_len < bytes.len() {
+            return Err(Error::Store(format!(
+                "Corrupted batch at offset {}",
+                offset
+            )));
+        }
+        return Ok(None);
+    }
+    let (root, records) = borsh::from_slice(payload).map_err(store_error)?;
+    Ok(Some((root, records, batch_len)))
+}
@@ -1,10 +1,13 @@
 use crate::InternalKey;
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// Represent 256 bits
 #[derive(Eq, PartialEq, Debug, Default, Hash, Clone, Copy, PartialOrd, Ord)]
 #[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct H256([u8; 32]);
 
 const ZERO: H256 = H256([0u8; 32]);
@@ -32,7 +35,7 @@ impl H256 {
     pub fn set_bit(&mut self, i: u8) {
         let byte_pos = MAX_INDEX - i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
-        self.0[byte_pos as usize] |= 1 << bit_pos as u8;
+        self.0[byte_pos as usize] |= 1 << bit_pos;
     }
 
     #[inline]
@@ -50,7 +53,7 @@ impl H256 {
     /// fork height is the number of common bits(from heigher to lower: 255..=0)
     /// of two H256
     pub fn fork_height(&self, key: &H256) -> u8 {
-        for h in (0..=core::u8::MAX).rev() {
+        for h in (0..=u8::MAX).rev() {
             if self.get_bit(h) != key.get_bit(h) {
                 return h;
             }
@@ -0,0 +1,133 @@
+use crate::{error::Error, traits::Hasher, Hash, InternalKey, Key, H256};
+#[cfg(feature = "borsh")]
+use crate::{string::ToString, vec::Vec};
+#[cfg(feature = "borsh")]
+use borsh::{
+    io::{Error as IoError, ErrorKind, Read, Write},
+    BorshDeserialize, BorshSerialize,
+};
+use core::{fmt, hash, marker::PhantomData, ops::Deref};
+
+/// Default limit of the original key size of `HashedKey`
+pub const HASHED_KEY_LIMIT: usize = 256;
+
+/// Key placed in the tree at the hash by H of its original bytes
+///
+/// The tree stays balanced whatever the distribution of the original keys,
+/// which are kept, up to L bytes, for `Key::as_slice`. The leaves commit to
+/// the hash, and the ICS23 proofs carry the original key with the hash as
+/// `LeafOp.prehash_key`, see `proof_ics23::get_prehashed_key_spec`. Hashers
+/// without an ICS23 hash operation, like `Blake2bHasher`, give no proofs.
+pub struct HashedKey<H, const L: usize = HASHED_KEY_LIMIT> {
+    hash: Hash,
+    original: [u8; L],
+    length: usize,
+    phantom: PhantomData<H>,
+}
+
+impl<H: Hasher + Default, const L: usize> HashedKey<H, L> {
+    /// return KeyTooLarge error if the key is longer than L bytes
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        if key.len() > L {
+            return Err(Error::KeyTooLarge);
+        }
+        let mut original = [0u8; L];
+        original[..key.len()].copy_from_slice(key);
+        let mut hasher = H::default();
+        hasher.write_bytes(key);
+        Ok(HashedKey {
+            hash: hasher.finish().into(),
+            original,
+            length: key.len(),
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<H, const L: usize> HashedKey<H, L> {
+    /// return the hash of the original key
+    pub fn hash(&self) -> H256 {
+        let bytes: [u8; 32] = (*self.hash).into();
+        bytes.into()
+    }
+}
+
+// the traits are implemented by hand to not require them from H
+
+impl<H, const L: usize> Clone for HashedKey<H, L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, const L: usize> Copy for HashedKey<H, L> {}
+
+impl<H, const L: usize> PartialEq for HashedKey<H, L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.original[..self.length] == other.original[..other.length]
+    }
+}
+
+impl<H, const L: usize> Eq for HashedKey<H, L> {}
+
+impl<H, const L: usize> hash::Hash for HashedKey<H, L> {
+    fn hash<S: hash::Hasher>(&self, state: &mut S) {
+        self.original[..self.length].hash(state);
+    }
+}
+
+impl<H, const L: usize> fmt::Debug for HashedKey<H, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashedKey")
+            .field("hash", &self.hash)
+            .field("original", &&self.original[..self.length])
+            .finish()
+    }
+}
+
+impl<H, const L: usize> Deref for HashedKey<H, L> {
+    type Target = InternalKey<32>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hash
+    }
+}
+
+impl<H: Hasher + Default, const L: usize> Key<32> for HashedKey<H, L> {
+    type Error = Error;
+
+    fn as_slice(&self) -> &[u8] {
+        &self.original[..self.length]
+    }
+
+    fn try_from_bytes(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::new(bytes)
+    }
+
+    fn is_prehashed() -> bool {
+        true
+    }
+
+    fn prehash_op() -> Option<ics23::HashOp> {
+        match H::hash_op() {
+            ics23::HashOp::NoHash => None,
+            hash_op => Some(hash_op),
+        }
+    }
+}
+
+/// Encoded as the original key bytes
+#[cfg(feature = "borsh")]
+impl<H, const L: usize> BorshSerialize for HashedKey<H, L> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        BorshSerialize::serialize(&self.original[..self.length], writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<H: Hasher + Default, const L: usize> BorshDeserialize for HashedKey<H, L> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        let bytes: Vec<u8> = BorshDeserialize::deserialize_reader(reader)?;
+        Self::new(&bytes).map_err(|err| IoError::new(ErrorKind::InvalidData, err.to_string()))
+    }
+}
@@ -1,11 +1,12 @@
 #[cfg(feature = "borsh")]
-use borsh::{BorshDeserialize, BorshSerialize};
+use crate::vec::Vec;
 #[cfg(feature = "borsh")]
-use core::convert::TryInto;
-use std::fmt::Debug;
-use std::io::Read;
+use borsh::{
+    io::{Error, ErrorKind, Read, Write},
+    BorshDeserialize, BorshSerialize,
+};
 #[cfg(feature = "borsh")]
-use std::io::Write;
+use core::convert::TryInto;
 
 /// The actual key value used in the tree
 #[derive(Eq, PartialEq, Debug, Hash, Clone, Copy, PartialOrd, Ord)]
@@ -13,7 +14,7 @@ pub struct InternalKey<const N: usize>([u8; N]);
 
 #[cfg(feature = "borsh")]
 impl<const N: usize> BorshSerialize for InternalKey<N> {
-    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
         let bytes = self.0.to_vec();
         BorshSerialize::serialize(&bytes, writer)
     }
@@ -21,12 +22,11 @@ impl<const N: usize> BorshSerialize for InternalKey<N> {
 
 #[cfg(feature = "borsh")]
 impl<const N: usize> BorshDeserialize for InternalKey<N> {
-    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
-        use std::io::ErrorKind;
+    fn deserialize_reader<R: Read>(reader: &mut R) -> borsh::io::Result<Self> {
         let bytes: Vec<u8> = BorshDeserialize::deserialize_reader(reader)?;
-        let bytes: [u8; N] = bytes.try_into().map_err(|_| {
-            std::io::Error::new(ErrorKind::InvalidData, "Input byte vector is too large")
-        })?;
+        let bytes: [u8; N] = bytes
+            .try_into()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Input byte vector is too large"))?;
         Ok(InternalKey(bytes))
     }
 }
@@ -52,9 +52,6 @@ impl<const N: usize> InternalKey<N> {
 
     #[inline]
     pub fn get_bit(&self, i: usize) -> bool {
-        if i / BYTE_SIZE > Self::max_index() {
-            println!("Hey");
-        }
         let byte_pos = Self::max_index() - i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
         let bit = self.0[byte_pos] >> bit_pos & 1;
@@ -65,27 +62,29 @@ impl<const N: usize> InternalKey<N> {
     pub fn set_bit(&mut self, i: usize) {
         let byte_pos = Self::max_index() - i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
-        self.0[byte_pos as usize] |= 1 << bit_pos as u8;
+        self.0[byte_pos] |= 1 << bit_pos as u8;
     }
 
     #[inline]
     pub fn clear_bit(&mut self, i: usize) {
         let byte_pos = Self::max_index() - i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
-        self.0[byte_pos as usize] &= !((1 << bit_pos) as u8);
+        self.0[byte_pos] &= !((1 << bit_pos) as u8);
     }
 
     /// Treat InternalKey as a path in a tree
     /// fork height is the number of common bits(from higher to lower)
     /// of two InternalKeys
     pub fn fork_height(&self, key: &InternalKey<N>) -> usize {
-        let max = (BYTE_SIZE * N) as usize;
-        for h in (0..max).rev() {
-            if self.get_bit(h) != key.get_bit(h) {
-                return h;
+        // the first different byte holds the highest different bit
+        match self.0.iter().zip(key.0.iter()).position(|(a, b)| a != b) {
+            Some(i) => {
+                let diff = self.0[i] ^ key.0[i];
+                let highest_bit = BYTE_SIZE - 1 - diff.leading_zeros() as usize;
+                (Self::max_index() - i) * BYTE_SIZE + highest_bit
             }
+            None => 0,
         }
-        0
     }
 
     /// Treat InternalKey as a path in a tree
@@ -99,20 +98,19 @@ impl<const N: usize> InternalKey<N> {
 
     /// Copy bits and return a new InternalKey
     pub fn copy_bits(&self, range: impl core::ops::RangeBounds<usize>) -> Self {
-        let array_size = N;
-        let max = 8 * N;
+        let max = BYTE_SIZE * N;
         use core::ops::Bound;
 
         let mut target = InternalKey::zero();
         let start = match range.start_bound() {
-            Bound::Included(&i) => i as usize,
+            Bound::Included(&i) => i,
             Bound::Excluded(&i) => panic!("do not allows excluded start: {}", i),
             Bound::Unbounded => 0,
         };
 
         let mut end = match range.end_bound() {
-            Bound::Included(&i) => i.saturating_add(1) as usize,
-            Bound::Excluded(&i) => i as usize,
+            Bound::Included(&i) => i.saturating_add(1),
+            Bound::Excluded(&i) => i,
             Bound::Unbounded => max,
         };
 
@@ -124,26 +122,17 @@ impl<const N: usize> InternalKey<N> {
 
         if end < start {
             panic!("end can't less than start: start {} end {}", start, end);
+        } else if end == start {
+            return target;
         }
 
-        let end_byte = {
-            let remain = if start % BYTE_SIZE != 0 { 1 } else { 0 };
-            array_size - start / BYTE_SIZE - remain
-        };
-        let start_byte = array_size - end / BYTE_SIZE;
-        // copy bytes
-        if start_byte < self.0.len() && start_byte <= end_byte {
-            target.0[start_byte..end_byte].copy_from_slice(&self.0[start_byte..end_byte]);
-        }
-
-        // copy remain bits
-        for i in (start..core::cmp::min((array_size - end_byte) * BYTE_SIZE, end))
-            .chain(core::cmp::max((array_size - start_byte) * BYTE_SIZE, start)..end)
-        {
-            if self.get_bit(i) {
-                target.set_bit(i)
-            }
-        }
+        // the bytes of the highest and the lowest bits, the higher bits come first
+        let high_byte = Self::max_index() - (end - 1) / BYTE_SIZE;
+        let low_byte = Self::max_index() - start / BYTE_SIZE;
+        target.0[high_byte..=low_byte].copy_from_slice(&self.0[high_byte..=low_byte]);
+        // clear the bits out of the range in the edge bytes
+        target.0[low_byte] &= u8::MAX << (start % BYTE_SIZE);
+        target.0[high_byte] &= u8::MAX >> (BYTE_SIZE - 1 - (end - 1) % BYTE_SIZE);
         target
     }
 }
@@ -0,0 +1,140 @@
+use crate::{
+    error::Result,
+    traits::{Store, Value},
+    vec::Vec,
+    InternalKey, Key, H256,
+};
+use core::{
+    cmp::Ordering,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+/// Iterator over the leaves of a tree in ascending key order
+///
+/// The branches are walked from the root, subtrees which are out of the range
+/// are skipped without being loaded from the store.
+pub struct Iter<'a, K, V, S, const N: usize>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    store: &'a S,
+    start: Bound<InternalKey<N>>,
+    end: Bound<InternalKey<N>>,
+    // nodes to visit, the next one on the top
+    stack: Vec<H256>,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, S, const N: usize> Iter<'a, K, V, S, N>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    /// Iterate the leaves of the tree with root, which keys are in the range
+    pub fn new(
+        store: &'a S,
+        root: &H256,
+        start: Bound<InternalKey<N>>,
+        end: Bound<InternalKey<N>>,
+    ) -> Self {
+        let mut stack = Vec::new();
+        if !root.is_zero() {
+            stack.push(*root);
+        }
+        Iter {
+            store,
+            start,
+            end,
+            stack,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Iterate the leaves which internal keys start with the prefix bytes
+    pub fn with_prefix(store: &'a S, root: &H256, prefix: &[u8]) -> Self {
+        if prefix.len() > N {
+            return Self::new(store, &H256::zero(), Bound::Unbounded, Bound::Unbounded);
+        }
+        let mut start = [0u8; N];
+        let mut end = [0xFF; N];
+        start[..prefix.len()].copy_from_slice(prefix);
+        end[..prefix.len()].copy_from_slice(prefix);
+        Self::new(
+            store,
+            root,
+            Bound::Included(start.into()),
+            Bound::Included(end.into()),
+        )
+    }
+
+    /// Check if the subtree which keys share the bits above height may
+    /// contain keys in the range
+    fn overlaps(&self, key: &InternalKey<N>, height: usize) -> bool {
+        let prefix = key.parent_path(height);
+        let after_start = match self.start {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                prefix.cmp(&start.parent_path(height)) != Ordering::Less
+            }
+            Bound::Unbounded => true,
+        };
+        let before_end = match self.end {
+            Bound::Included(end) | Bound::Excluded(end) => {
+                prefix.cmp(&end.parent_path(height)) != Ordering::Greater
+            }
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    fn next_leaf(&mut self) -> Result<Option<(K, V)>> {
+        while let Some(node) = self.stack.pop() {
+            let branch = match self.store.get_branch(&node)? {
+                Some(branch) => branch,
+                None => continue,
+            };
+            if branch.is_leaf() {
+                match self.store.get_leaf(&node)? {
+                    Some(leaf) if (self.start, self.end).contains(&*leaf.key) => {
+                        return Ok(Some((leaf.key, leaf.value)));
+                    }
+                    _ => continue,
+                }
+            }
+            if !self.overlaps(&branch.key, branch.fork_height) {
+                continue;
+            }
+            // visit the left side first
+            let (left, right) = branch.branch(branch.fork_height);
+            for child in [right, left].iter() {
+                if !child.is_zero() {
+                    self.stack.push(**child);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, K, V, S, const N: usize> Iterator for Iter<'a, K, V, S, N>
+where
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_leaf() {
+            Ok(leaf) => leaf.map(Ok),
+            Err(err) => {
+                // stop the iteration after a store error
+                self.stack.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Keccak256 hasher for trees whose roots are verified on EVM chains
+//!
+//! With `Keccak256Hasher`, not domain separated, a leaf hash is
+//! `keccak256(abi.encodePacked(bytes32(0), key, value))` and a branch hash
+//! `keccak256(abi.encodePacked(lhs, rhs))`, a zero side being skipped. A
+//! `SolidityProof` of a leaf is verified by `SolidityProof::verify` the same way
+//! as by this contract function:
+//!
+//! ```solidity
+//! function verify(
+//!     bytes32 root,
+//!     bytes32 key,
+//!     bytes32 value,
+//!     uint256 bitmap,
+//!     bytes32[] calldata siblings
+//! ) pure returns (bool) {
+//!     if (value == bytes32(0)) return false;
+//!     bytes32 node = keccak256(abi.encodePacked(bytes32(0), key, value));
+//!     uint256 next = 0;
+//!     for (uint256 height = 0; height < 256; height++) {
+//!         if ((bitmap >> height) & 1 == 0) continue;
+//!         if (next == siblings.length) return false;
+//!         bytes32 sibling = siblings[next++];
+//!         if ((uint256(key) >> height) & 1 == 1) {
+//!             node = merge(sibling, node);
+//!         } else {
+//!             node = merge(node, sibling);
+//!         }
+//!     }
+//!     return next == siblings.length && node == root;
+//! }
+//!
+//! function merge(bytes32 lhs, bytes32 rhs) pure returns (bytes32) {
+//!     if (lhs == bytes32(0)) return rhs;
+//!     if (rhs == bytes32(0)) return lhs;
+//!     return keccak256(abi.encodePacked(lhs, rhs));
+//! }
+//! ```
+
+use crate::{
+    error::{Error, Result},
+    merkle_proof::MerkleProof,
+    traits::Hasher,
+    vec::Vec,
+    Hash, H256,
+};
+use sha3::{Digest, Keccak256};
+
+#[derive(Default)]
+pub struct Keccak256Hasher(Keccak256);
+
+impl Hasher for Keccak256Hasher {
+    fn write_bytes(&mut self, h: &[u8]) {
+        self.0.update(h);
+    }
+
+    fn finish(self) -> H256 {
+        let hash: [u8; 32] = self.0.finalize().into();
+        hash.into()
+    }
+
+    fn hash_op() -> ics23::HashOp {
+        ics23::HashOp::Keccak256
+    }
+}
+
+/// Merkle proof of a single leaf in the layout of the Solidity verifier
+///
+/// Bit h of the bitmap, as a big endian uint256, is set when the leaf is
+/// merged at height h with the next sibling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolidityProof {
+    bitmap: H256,
+    siblings: Vec<H256>,
+}
+
+impl SolidityProof {
+    pub fn new(bitmap: H256, siblings: Vec<H256>) -> Self {
+        SolidityProof { bitmap, siblings }
+    }
+
+    /// Convert the merkle proof of a single leaf of a tree with 32 bytes keys
+    ///
+    /// return IncorrectNumberOfLeaves error if the proof has several leaves
+    pub fn from_merkle_proof(merkle_proof: &MerkleProof) -> Result<Self> {
+        if merkle_proof.leaves_count() != 1 {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected: 1,
+                actual: merkle_proof.leaves_count(),
+            });
+        }
+        merkle_proof.check::<32>()?;
+        // a single leaf is merged with a sibling at each height of the proof,
+        // the path of the leaf of a single leaf tree has no sibling
+        let proof = merkle_proof.proof();
+        let mut bitmap = H256::zero();
+        for (_sibling, height) in proof {
+            bitmap.set_bit(*height as u8);
+        }
+        let siblings = proof.iter().map(|(sibling, _height)| *sibling).collect();
+        Ok(SolidityProof { bitmap, siblings })
+    }
+
+    pub fn bitmap(&self) -> &H256 {
+        &self.bitmap
+    }
+
+    pub fn siblings(&self) -> &[H256] {
+        &self.siblings
+    }
+
+    /// Verify the leaf against the root like the Solidity verifier
+    ///
+    /// A zero value is rejected, the proof can't show a key is absent.
+    pub fn verify(&self, root: &H256, key: &Hash, value: &H256) -> bool {
+        if value.is_zero() {
+            return false;
+        }
+        let mut hasher = Keccak256::new();
+        hasher.update(H256::zero().as_slice());
+        hasher.update(key.as_slice());
+        hasher.update(value.as_slice());
+        let mut node = keccak256_node(hasher);
+        let mut siblings = self.siblings.iter();
+        for height in 0..=u8::MAX {
+            if !self.bitmap.get_bit(height) {
+                continue;
+            }
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            node = if key.get_bit(height.into()) {
+                solidity_merge(sibling, &node)
+            } else {
+                solidity_merge(&node, sibling)
+            };
+        }
+        siblings.next().is_none() && &node == root
+    }
+}
+
+fn solidity_merge(lhs: &H256, rhs: &H256) -> H256 {
+    if lhs.is_zero() {
+        return *rhs;
+    } else if rhs.is_zero() {
+        return *lhs;
+    }
+    let mut hasher = Keccak256::new();
+    hasher.update(lhs.as_slice());
+    hasher.update(rhs.as_slice());
+    keccak256_node(hasher)
+}
+
+fn keccak256_node(hasher: Keccak256) -> H256 {
+    let hash: [u8; 32] = hasher.finalize().into();
+    hash.into()
+}
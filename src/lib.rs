@@ -3,7 +3,7 @@
 //! # Examples
 //!
 //! ```
-//! use sparse_merkle_tree::{
+//! use nam_sparse_merkle_tree::{
 //!     blake2b::Blake2bHasher, default_store::DefaultStore,
 //!     error::Error, MerkleProof,
 //!     SparseMerkleTree, traits::Value, H256, Hash,
@@ -65,39 +65,64 @@
 
 #[cfg(feature = "blake2b")]
 pub mod blake2b;
+#[cfg(feature = "blake2s")]
+pub mod blake2s;
+pub mod blob_store;
+#[cfg(feature = "borsh")]
+pub mod borsh_value;
 pub mod default_store;
+pub mod diff;
 pub mod error;
+#[cfg(all(feature = "std", feature = "borsh"))]
+pub mod file_store;
 pub mod h256;
+pub mod hashed_key;
 pub mod internal_key;
+pub mod iter;
+#[cfg(feature = "keccak256")]
+pub mod keccak256;
 pub mod merge;
 pub mod merkle_proof;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
 pub mod proof_ics23;
 pub mod sha256;
+pub mod snapshot;
 #[cfg(test)]
 mod tests;
 pub mod traits;
+pub mod transaction;
 pub mod tree;
+pub mod versioned_store;
+pub mod witness;
 
 pub use h256::{Hash, H256};
+pub use hashed_key::HashedKey;
 pub use internal_key::InternalKey;
-pub use merkle_proof::{CompiledMerkleProof, MerkleProof};
+pub use merkle_proof::{
+    CompiledMerkleProof, MerkleProof, NonMembershipProof, RangeProof, UpdateProof,
+};
 pub use traits::Key;
 pub use tree::SparseMerkleTree;
 
 /// Expected path size: log2(256) * 2, used for hint vector capacity
 pub const EXPECTED_PATH_SIZE: usize = 16;
-/// Height of sparse merkle tree
+/// Height of sparse merkle tree with 32 bytes keys,
+/// a tree with N bytes keys has height 8 * N
 pub const TREE_HEIGHT: usize = 256;
 /// Key limit size
 pub const KEY_LIMIT: usize = 4_294_967_295u32 as usize;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "std")] {
+        use std::boxed;
         use std::collections;
         use std::vec;
         use std::string;
     } else {
+        #[macro_use]
         extern crate alloc;
+        use alloc::boxed;
         use alloc::collections;
         use alloc::vec;
         use alloc::string;
@@ -2,22 +2,57 @@ use crate::h256::H256;
 use crate::traits::{Hasher, Value};
 use crate::Key;
 
-/// Merge two hashes
+/// Prefix of the leaf hash preimages in domain separated hashing
+pub const LEAF_PREFIX: u8 = 0x00;
+/// Prefix of the branch hash preimages in domain separated hashing
+pub const BRANCH_PREFIX: u8 = 0x01;
+
+/// Hasher wrapper which opts into domain separated hashing
+///
+/// Leaves are hashed as hash(LEAF_PREFIX | key | value) and the branches
+/// merged at height as hash(BRANCH_PREFIX | height | lhs | rhs), with the
+/// height as 8 big endian bytes. A branch with a zero side is still the other
+/// side, its hash commits to the key of its single leaf or to its height.
+#[derive(Default)]
+pub struct DomainSeparated<H>(H);
+
+impl<H: Hasher> Hasher for DomainSeparated<H> {
+    fn write_bytes(&mut self, h: &[u8]) {
+        self.0.write_bytes(h);
+    }
+    fn finish(self) -> H256 {
+        self.0.finish()
+    }
+    fn hash_op() -> ics23::HashOp {
+        H::hash_op()
+    }
+    fn is_domain_separated() -> bool {
+        true
+    }
+}
+
+/// Merge two hashes at height
 /// this function is optimized for ZERO_HASH
 /// if one of lhs or rhs is ZERO_HASH, this function just returns the another one
-pub fn merge<H: Hasher + Default>(lhs: &H256, rhs: &H256) -> H256 {
+pub fn merge<H: Hasher + Default>(height: usize, lhs: &H256, rhs: &H256) -> H256 {
     if lhs.is_zero() {
         return *rhs;
     } else if rhs.is_zero() {
         return *lhs;
     }
     let mut hasher = H::default();
+    if H::is_domain_separated() {
+        hasher.write_bytes(&[BRANCH_PREFIX]);
+        hasher.write_bytes(&(height as u64).to_be_bytes());
+    }
     hasher.write_bytes(lhs.as_slice());
     hasher.write_bytes(rhs.as_slice());
     hasher.finish()
 }
 
-/// hash_leaf = hash(prefix | key | value)
+/// hash_leaf = hash(prefix | key | value), the prefix is 32 zero bytes or LEAF_PREFIX
+/// with domain separated hashing, the key is the internal key of prehashed keys
+/// and the value is hash(value) for prehashed values
 /// zero value indicates a key is to be deleted, this function returns zero for zero value
 pub fn hash_leaf<H: Hasher + Default, K, V, const N: usize>(key: &K, value: &V) -> H256
 where
@@ -28,8 +63,22 @@ where
         return H256::zero();
     }
     let mut hasher = H::default();
-    hasher.write_bytes(H256::zero().as_slice());
-    hasher.write_bytes(key.as_slice());
-    hasher.write_bytes(value.as_slice());
+    if H::is_domain_separated() {
+        hasher.write_bytes(&[LEAF_PREFIX]);
+    } else {
+        hasher.write_bytes(H256::zero().as_slice());
+    }
+    if K::is_prehashed() {
+        hasher.write_bytes((**key).as_slice());
+    } else {
+        hasher.write_bytes(key.as_slice());
+    }
+    if V::is_prehashed() {
+        let mut value_hasher = H::default();
+        value_hasher.write_bytes(value.as_slice());
+        hasher.write_bytes(value_hasher.finish().as_slice());
+    } else {
+        hasher.write_bytes(value.as_slice());
+    }
     hasher.finish()
 }
@@ -1,16 +1,44 @@
+#[cfg(feature = "borsh")]
+use crate::string::ToString;
 use crate::{
     collections::{BTreeMap, VecDeque},
     error::{Error, Result},
     merge::{hash_leaf, merge},
     traits::{Hasher, Value},
     vec::Vec,
-    Key, H256, TREE_HEIGHT,
+    InternalKey, Key, H256,
 };
-use core::convert::TryInto;
+#[cfg(feature = "borsh")]
+use borsh::{
+    io::{Error as IoError, ErrorKind, Read, Write},
+    BorshDeserialize, BorshSerialize,
+};
+use core::convert::{TryFrom, TryInto};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 type Range = core::ops::Range<usize>;
 
+/// Version of the MerkleProof and CompiledMerkleProof wire encodings
+pub const PROOF_ENCODING_VERSION: u8 = 1;
+
+/// Height of the tree with the longest keys, bounds the heights of decoded proofs
+///
+/// The decoded proofs don't know the key width N of their tree, so their
+/// heights are only checked against the longest keys. `MerkleProof::decode` and
+/// `CompiledMerkleProof::decode` check them against the tree height 8 * N, and
+/// every function reading the heights of a proof for a tree with N bytes keys
+/// checks them with `MerkleProof::check::<N>` or
+/// `CompiledMerkleProof::check::<N>` first.
+#[cfg(any(feature = "borsh", feature = "serde"))]
+const MAX_TREE_HEIGHT: usize = crate::KEY_LIMIT.saturating_mul(8);
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "MerkleProofEncoding", into = "MerkleProofEncoding")
+)]
 pub struct MerkleProof {
     leaves_path: Vec<Vec<usize>>,
     proof: Vec<(H256, usize)>,
@@ -45,6 +73,95 @@ impl MerkleProof {
         &self.proof
     }
 
+    /// Check the proof is well formed for a tree with N bytes keys
+    ///
+    /// The heights must be below the tree height 8 * N, the heights of each leaf
+    /// must be strictly increasing and the proof must agree with the leaves path:
+    /// each proof node is merged at a height of a leaf path, and the other
+    /// heights of the paths are merges of two leaves.
+    pub fn check<const N: usize>(&self) -> Result<()> {
+        check_proof(&self.leaves_path, &self.proof, 8 * N)
+    }
+
+    /// Decode the borsh encoding of a proof for a tree with N bytes keys
+    ///
+    /// The decoded proof is checked for the tree, see `check`, while
+    /// `BorshDeserialize` only bounds the heights by the longest keys.
+    /// return CorruptedProof error if the bytes are not a proof encoding
+    #[cfg(feature = "borsh")]
+    pub fn decode<const N: usize>(bytes: &[u8]) -> Result<Self> {
+        let proof: Self = borsh::from_slice(bytes).map_err(|_| Error::CorruptedProof)?;
+        proof.check::<N>()?;
+        Ok(proof)
+    }
+
+    /// Check no leaf of the tree lies between the proven keys, sorted in
+    /// ascending order, nor before the first one with left_edge or after the
+    /// last one with right_edge
+    pub(crate) fn is_contiguous<K, const N: usize>(
+        &self,
+        keys: &[K],
+        left_edge: bool,
+        right_edge: bool,
+    ) -> bool
+    where
+        K: Key<N>,
+    {
+        let len = keys.len();
+        self.has_empty_gaps::<K, _, N>(keys, |gap| match gap {
+            0 => left_edge,
+            gap if gap == len => right_edge,
+            _ => true,
+        })
+    }
+
+    /// Check no leaf of the tree lies in the gaps between the proven keys,
+    /// sorted in ascending order, which must be empty
+    ///
+    /// The gap i lies before the key i and after the key i - 1 if any, the
+    /// gap keys.len() after the last key. The merges of each leaf path are
+    /// checked: a subtree merged with a node of the proof lies in a single
+    /// gap. Each key but the first one must end its path where it forks with
+    /// the previous key. The proof must be well formed for the tree, see
+    /// `check`, and compute the root for the heights to be the ones of the
+    /// merges.
+    pub(crate) fn has_empty_gaps<K, F, const N: usize>(&self, keys: &[K], must_be_empty: F) -> bool
+    where
+        K: Key<N>,
+        F: Fn(usize) -> bool,
+    {
+        if keys.is_empty() || self.leaves_path.len() != keys.len() || self.check::<N>().is_err() {
+            return false;
+        }
+        let joined = keys
+            .windows(2)
+            .zip(self.leaves_path.iter().skip(1))
+            .all(|(pair, path)| path.last() == Some(&pair[0].fork_height(&pair[1])));
+        if !joined {
+            return false;
+        }
+        // the proven keys are the whole tree
+        if self.proof.is_empty() {
+            return true;
+        }
+        keys.iter().zip(self.leaves_path.iter()).all(|(key, path)| {
+            path.iter().all(|height| {
+                let height = *height;
+                // the merged sibling, a subtree of proven keys or a proof node
+                let mut sibling = key.parent_path(height);
+                if !key.get_bit(height) {
+                    sibling.set_bit(height);
+                }
+                let index = keys.partition_point(|k| k.copy_bits(height..) < sibling);
+                let has_keys = keys
+                    .get(index)
+                    .is_some_and(|k| k.copy_bits(height..) == sibling);
+                // the proof node lies in the gap before the next proven key
+                has_keys || !must_be_empty(index)
+            })
+        })
+    }
+
     /// convert merkle proof into CompiledMerkleProof
     pub fn compile<K, const N: usize>(
         self,
@@ -61,8 +178,10 @@ impl MerkleProof {
                 actual: leaves.len(),
             });
         }
+        self.check::<N>()?;
 
         let (leaves_path, proof) = self.take();
+        let single_leaf = is_single_leaf(&leaves_path, &proof);
         let mut leaves_path: Vec<VecDeque<_>> = leaves_path.into_iter().map(Into::into).collect();
         let mut proof: VecDeque<_> = proof.into();
 
@@ -77,16 +196,16 @@ impl MerkleProof {
         // rebuild the tree from bottom to top
         while !tree_buf.is_empty() {
             // pop_front from tree_buf, the API is unstable
-            let &(mut height, key) = tree_buf.keys().next().unwrap();
+            let &(height, key) = tree_buf.keys().next().unwrap();
             let (leaf_index, program) = tree_buf.remove(&(height, key)).unwrap();
 
             if proof.is_empty() && tree_buf.is_empty() {
-                return Ok(CompiledMerkleProof(program.0));
-            } else if height == TREE_HEIGHT {
-                if !proof.is_empty() {
+                if !single_leaf && leaves_path.iter().any(|path| !path.is_empty()) {
                     return Err(Error::CorruptedProof);
                 }
                 return Ok(CompiledMerkleProof(program.0));
+            } else if height == 8 * N {
+                return Err(Error::CorruptedProof);
             }
 
             let mut sibling_key = key.parent_path(height);
@@ -94,38 +213,24 @@ impl MerkleProof {
                 sibling_key.set_bit(height)
             }
 
-            let (parent_key, parent_program, height) =
-                if Some(&(height, sibling_key)) == tree_buf.keys().next() {
-                    let (_leaf_index, sibling_program) = tree_buf
-                        .remove(&(height, sibling_key))
-                        .expect("pop sibling");
-                    let parent_key = key.parent_path(height);
-                    let parent_program = merge_program(&program, &sibling_program, height)?;
-                    (parent_key, parent_program, height)
-                } else {
-                    let merge_height = leaves_path[leaf_index]
-                        .front()
-                        .map(|h| *h as usize)
-                        .unwrap_or(height);
-                    if height != merge_height {
-                        debug_assert!(height < merge_height);
-                        let parent_key = key.copy_bits(merge_height..);
-                        // skip zeros
-                        tree_buf.insert((merge_height, parent_key), (leaf_index, program));
-                        continue;
-                    }
-                    let (proof, proof_height) = proof.pop_front().expect("pop proof");
-                    debug_assert_eq!(proof_height, leaves_path[leaf_index][0]);
-                    let proof_height = proof_height as usize;
-                    debug_assert!(height <= proof_height);
-                    if height < proof_height {
-                        height = proof_height;
-                    }
-
-                    let parent_key = key.parent_path(height);
-                    let parent_program = proof_program(&program, proof, height);
-                    (parent_key, parent_program, height)
-                };
+            let parent_program = if Some(&(height, sibling_key)) == tree_buf.keys().next() {
+                let (sibling_index, sibling_program) = tree_buf
+                    .remove(&(height, sibling_key))
+                    .expect("pop sibling");
+                merge_leaves_paths(&mut leaves_path, leaf_index, sibling_index, height)?;
+                merge_program(&program, &sibling_program, height)?
+            } else {
+                let merge_height = next_merge_height(&leaves_path[leaf_index], height)?;
+                if height != merge_height {
+                    let parent_key = key.copy_bits(merge_height..);
+                    // skip zeros
+                    tree_buf.insert((merge_height, parent_key), (leaf_index, program));
+                    continue;
+                }
+                let proof = pop_proof(&mut proof, height)?;
+                proof_program(&program, proof, height)
+            };
+            let parent_key = key.parent_path(height);
 
             leaves_path[leaf_index].pop_front();
             tree_buf.insert((height + 1, parent_key), (leaf_index, parent_program));
@@ -140,12 +245,29 @@ impl MerkleProof {
     /// return EmptyProof error when proof is empty
     /// return CorruptedProof error when proof is invalid
     pub fn compute_root<H: Hasher + Default, K, V, const N: usize>(
+        self,
+        leaves: Vec<(K, V)>,
+    ) -> Result<H256>
+    where
+        K: Key<N>,
+        V: Value,
+    {
+        self.compute_root_with::<H, K, V, _, N>(leaves, |_height, _leaves, _sibling| {})
+    }
+
+    /// Compute root from proof, on_merge is called with the height, the
+    /// indexes of the sorted leaves under a node and its non zero sibling
+    /// for each merge
+    pub(crate) fn compute_root_with<H, K, V, F, const N: usize>(
         self,
         mut leaves: Vec<(K, V)>,
+        mut on_merge: F,
     ) -> Result<H256>
     where
+        H: Hasher + Default,
         K: Key<N>,
         V: Value,
+        F: FnMut(usize, Range, &H256),
     {
         if leaves.is_empty() {
             return Err(Error::EmptyKeys);
@@ -155,74 +277,78 @@ impl MerkleProof {
                 actual: leaves.len(),
             });
         }
+        self.check::<N>()?;
 
         let (leaves_path, proof) = self.take();
+        let single_leaf = is_single_leaf(&leaves_path, &proof);
         let mut leaves_path: Vec<VecDeque<_>> = leaves_path.into_iter().map(Into::into).collect();
         let mut proof: VecDeque<_> = proof.into();
 
         // sort leaves
         leaves.sort_unstable_by_key(|(k, _v)| **k);
-        // tree_buf: (height, key) -> (key_index, node)
+        // tree_buf: (height, key) -> (key_index, leaves indexes, node)
         let mut tree_buf: BTreeMap<_, _> = leaves
             .into_iter()
             .enumerate()
-            .map(|(i, (k, v))| ((0, *k), (i, hash_leaf::<H, K, V, N>(&k, &v))))
+            .map(|(i, (k, v))| ((0, *k), (i, i..i + 1, hash_leaf::<H, K, V, N>(&k, &v))))
             .collect();
         // rebuild the tree from bottom to top
         while !tree_buf.is_empty() {
             // pop_front from tree_buf, the API is unstable
-            let (&(mut height, key), &(leaf_index, node)) = tree_buf.iter().next().unwrap();
+            let (&(height, key), (leaf_index, range, node)) = tree_buf.iter().next().unwrap();
+            let (leaf_index, mut range, node) = (*leaf_index, range.clone(), *node);
             tree_buf.remove(&(height, key));
 
             if proof.is_empty() && tree_buf.is_empty() {
-                return Ok(node);
-            } else if height == 8 * N {
-                if !proof.is_empty() {
+                if !single_leaf && leaves_path.iter().any(|path| !path.is_empty()) {
                     return Err(Error::CorruptedProof);
                 }
                 return Ok(node);
+            } else if height == 8 * N {
+                return Err(Error::CorruptedProof);
             }
 
             let mut sibling_key = key.parent_path(height);
             if !key.get_bit(height) {
                 sibling_key.set_bit(height)
             }
-            let (sibling, sibling_height) =
-                if Some(&(height, sibling_key)) == tree_buf.keys().next() {
-                    let (_leaf_index, sibling) = tree_buf
-                        .remove(&(height, sibling_key))
-                        .expect("pop sibling");
-                    (sibling, height)
-                } else {
-                    let merge_height = leaves_path[leaf_index]
-                        .front()
-                        .map(|h| *h as usize)
-                        .unwrap_or(height);
-                    if height != merge_height {
-                        debug_assert!(height < merge_height);
-                        let parent_key = key.copy_bits(merge_height..);
-                        // skip zeros
-                        tree_buf.insert((merge_height, parent_key), (leaf_index, node));
-                        continue;
-                    }
-                    let (node, height) = proof.pop_front().expect("pop proof");
-                    debug_assert_eq!(height, leaves_path[leaf_index][0]);
-                    (node, height as usize)
-                };
-            debug_assert!(height <= sibling_height);
-            if height < sibling_height {
-                height = sibling_height;
-            }
+            let sibling = if Some(&(height, sibling_key)) == tree_buf.keys().next() {
+                let (sibling_index, sibling_range, sibling) = tree_buf
+                    .remove(&(height, sibling_key))
+                    .expect("pop sibling");
+                merge_leaves_paths(&mut leaves_path, leaf_index, sibling_index, height)?;
+                if !sibling.is_zero() {
+                    on_merge(height, range.clone(), &sibling);
+                }
+                if !node.is_zero() {
+                    on_merge(height, sibling_range.clone(), &node);
+                }
+                range.end = sibling_range.end;
+                sibling
+            } else {
+                let merge_height = next_merge_height(&leaves_path[leaf_index], height)?;
+                if height != merge_height {
+                    let parent_key = key.copy_bits(merge_height..);
+                    // skip zeros
+                    tree_buf.insert((merge_height, parent_key), (leaf_index, range, node));
+                    continue;
+                }
+                let sibling = pop_proof(&mut proof, height)?;
+                if !sibling.is_zero() {
+                    on_merge(height, range.clone(), &sibling);
+                }
+                sibling
+            };
             // skip zero merkle path
             let parent_key = key.parent_path(height);
 
             let parent = if key.get_bit(height) {
-                merge::<H>(&sibling, &node)
+                merge::<H>(height, &sibling, &node)
             } else {
-                merge::<H>(&node, &sibling)
+                merge::<H>(height, &node, &sibling)
             };
             leaves_path[leaf_index].pop_front();
-            tree_buf.insert((height + 1, parent_key), (leaf_index, parent));
+            tree_buf.insert((height + 1, parent_key), (leaf_index, range, parent));
         }
 
         Err(Error::CorruptedProof)
@@ -244,6 +370,151 @@ impl MerkleProof {
     }
 }
 
+/// the single leaf of a tree has no merge to consume from its path
+fn is_single_leaf(leaves_path: &[Vec<usize>], proof: &[(H256, usize)]) -> bool {
+    leaves_path.len() == 1 && proof.is_empty()
+}
+
+/// return the height of the next merge of a node at height, it can't be
+/// below the node nor missing before the root
+fn next_merge_height(leaf_path: &VecDeque<usize>, height: usize) -> Result<usize> {
+    match leaf_path.front() {
+        Some(merge_height) if *merge_height >= height => Ok(*merge_height),
+        _ => Err(Error::CorruptedProof),
+    }
+}
+
+/// return the next proof node, which must be merged at height
+fn pop_proof(proof: &mut VecDeque<(H256, usize)>, height: usize) -> Result<H256> {
+    match proof.pop_front() {
+        Some((node, proof_height)) if proof_height == height => Ok(node),
+        _ => Err(Error::CorruptedProof),
+    }
+}
+
+/// Check both leaves paths merge two nodes at height and end the path of the
+/// right node, the left one is popped with its parent
+fn merge_leaves_paths(
+    leaves_path: &mut [VecDeque<usize>],
+    leaf_index: usize,
+    sibling_index: usize,
+    height: usize,
+) -> Result<()> {
+    if leaves_path[leaf_index].front() != Some(&height) || leaves_path[sibling_index] != [height] {
+        return Err(Error::CorruptedProof);
+    }
+    leaves_path[sibling_index].clear();
+    Ok(())
+}
+
+fn check_proof(
+    leaves_path: &[Vec<usize>],
+    proof: &[(H256, usize)],
+    tree_height: usize,
+) -> Result<()> {
+    // height -> number of merges of the leaves path
+    let mut merges: BTreeMap<usize, usize> = BTreeMap::new();
+    for path in leaves_path {
+        let increasing = path.windows(2).all(|heights| heights[0] < heights[1]);
+        let in_tree = path.last().is_none_or(|height| *height < tree_height);
+        if !increasing || !in_tree {
+            return Err(Error::CorruptedProof);
+        }
+        for height in path {
+            *merges.entry(*height).or_default() += 1;
+        }
+    }
+    // the single leaf of a tree is only merged with zeros, its path is at
+    // most the top height of the tree
+    if is_single_leaf(leaves_path, proof) {
+        if leaves_path[0].len() > 1 {
+            return Err(Error::CorruptedProof);
+        }
+        return Ok(());
+    }
+    // every proof node is merged at a height of the leaves path
+    for (_node, height) in proof {
+        match merges.get_mut(height) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => return Err(Error::CorruptedProof),
+        }
+    }
+    // the other heights are merges of two leaves, which appear in both paths,
+    // each leaf but one is merged with another
+    let leaves_merges: usize = merges.values().sum();
+    if merges.values().any(|count| count % 2 != 0)
+        || leaves_merges != 2 * leaves_path.len().saturating_sub(1)
+    {
+        return Err(Error::CorruptedProof);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for MerkleProof {
+    fn serialize<W: Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        BorshSerialize::serialize(&PROOF_ENCODING_VERSION, writer)?;
+        BorshSerialize::serialize(&self.leaves_path, writer)?;
+        BorshSerialize::serialize(&self.proof, writer)
+    }
+}
+
+/// The heights are only bounded by the longest keys, see `MerkleProof::decode`
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for MerkleProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        check_version(BorshDeserialize::deserialize_reader(reader)?)?;
+        let leaves_path: Vec<Vec<usize>> = BorshDeserialize::deserialize_reader(reader)?;
+        let proof: Vec<(H256, usize)> = BorshDeserialize::deserialize_reader(reader)?;
+        check_proof(&leaves_path, &proof, MAX_TREE_HEIGHT)
+            .map_err(|err| IoError::new(ErrorKind::InvalidData, err.to_string()))?;
+        Ok(MerkleProof { leaves_path, proof })
+    }
+}
+
+#[cfg(feature = "borsh")]
+fn check_version(version: u8) -> borsh::io::Result<()> {
+    if version != PROOF_ENCODING_VERSION {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            "Unsupported proof encoding version",
+        ));
+    }
+    Ok(())
+}
+
+/// Versioned serde encoding of MerkleProof
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+enum MerkleProofEncoding {
+    V1 {
+        leaves_path: Vec<Vec<usize>>,
+        proof: Vec<(H256, usize)>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl From<MerkleProof> for MerkleProofEncoding {
+    fn from(merkle_proof: MerkleProof) -> Self {
+        let (leaves_path, proof) = merkle_proof.take();
+        MerkleProofEncoding::V1 { leaves_path, proof }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<MerkleProofEncoding> for MerkleProof {
+    type Error = Error;
+
+    fn try_from(encoding: MerkleProofEncoding) -> Result<Self> {
+        match encoding {
+            MerkleProofEncoding::V1 { leaves_path, proof } => {
+                check_proof(&leaves_path, &proof, MAX_TREE_HEIGHT)?;
+                Ok(MerkleProof { leaves_path, proof })
+            }
+        }
+    }
+}
+
 fn leaf_program(leaf_index: usize) -> (Vec<u8>, Option<Range>) {
     let program = vec![0x4C];
     (
@@ -313,11 +584,423 @@ fn merge_program(
     Ok((program, Some(range)))
 }
 
+/// Merkle proof that a key is absent from a tree
+///
+/// The proof opens the existing leaves next to the key, the greatest smaller
+/// key and the least greater key, with a merkle proof showing no leaf of the
+/// tree lies between them. It is verified without ICS23 types.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NonMembershipProof<K, V> {
+    left: Option<(K, V)>,
+    right: Option<(K, V)>,
+    merkle_proof: MerkleProof,
+}
+
+impl<K, V> NonMembershipProof<K, V> {
+    /// left: the leaf before the key, if any
+    /// right: the leaf after the key, if any
+    /// merkle_proof: the merkle proof of the existing left and right leaves
+    pub fn new(left: Option<(K, V)>, right: Option<(K, V)>, merkle_proof: MerkleProof) -> Self {
+        NonMembershipProof {
+            left,
+            right,
+            merkle_proof,
+        }
+    }
+
+    /// Destruct the structure, useful for serialization
+    #[allow(clippy::type_complexity)]
+    pub fn take(self) -> (Option<(K, V)>, Option<(K, V)>, MerkleProof) {
+        let NonMembershipProof {
+            left,
+            right,
+            merkle_proof,
+        } = self;
+        (left, right, merkle_proof)
+    }
+
+    /// return the leaf before the key
+    pub fn left(&self) -> Option<&(K, V)> {
+        self.left.as_ref()
+    }
+
+    /// return the leaf after the key
+    pub fn right(&self) -> Option<&(K, V)> {
+        self.right.as_ref()
+    }
+
+    /// return the merkle proof of the neighbor leaves
+    pub fn merkle_proof(&self) -> &MerkleProof {
+        &self.merkle_proof
+    }
+
+    /// Verify the key is absent from the tree with root
+    ///
+    /// return false when the neighbors are not existing leaves around the key
+    /// or when they are not adjacent in the tree
+    pub fn verify<H: Hasher + Default, const N: usize>(&self, root: &H256, key: &K) -> Result<bool>
+    where
+        K: Key<N>,
+        V: Value,
+    {
+        let leaves: Vec<(K, V)> = self.left.iter().chain(self.right.iter()).cloned().collect();
+        if leaves.iter().any(|(_k, v)| v.is_zero()) {
+            return Ok(false);
+        }
+        let left_of_key = self.left.as_ref().is_none_or(|(k, _v)| **k < **key);
+        let right_of_key = self.right.as_ref().is_none_or(|(k, _v)| **k > **key);
+        if !left_of_key || !right_of_key {
+            return Ok(false);
+        }
+        if leaves.is_empty() {
+            // nothing exists in an empty tree
+            return Ok(root.is_zero());
+        }
+        if !self.is_adjacent::<N>() {
+            return Ok(false);
+        }
+        let calculated_root = self
+            .merkle_proof
+            .clone()
+            .compute_root::<H, K, V, N>(leaves)?;
+        Ok(&calculated_root == root)
+    }
+
+    /// Check no leaf can lie between the neighbors
+    ///
+    /// Under the height where the neighbors fork, the left leaf may only have
+    /// siblings on its left and the right leaf only siblings on its right.
+    fn is_adjacent<const N: usize>(&self) -> bool
+    where
+        K: Key<N>,
+    {
+        if self.merkle_proof.check::<N>().is_err() {
+            return false;
+        }
+        let leaves_path = self.merkle_proof.leaves_path();
+        match (&self.left, &self.right) {
+            (Some((left, _)), Some((right, _))) => {
+                let fork_height = left.fork_height(right);
+                leaves_path.len() == 2
+                    && leaves_path[0].contains(&fork_height)
+                    && leaves_path[1].last() == Some(&fork_height)
+                    && leaves_path[0]
+                        .iter()
+                        .take_while(|height| **height < fork_height)
+                        .all(|height| left.get_bit(*height))
+                    && leaves_path[1]
+                        .iter()
+                        .take_while(|height| **height < fork_height)
+                        .all(|height| !right.get_bit(*height))
+            }
+            (Some((left, _)), None) => {
+                // the leaf path of a single leaf tree has no sibling
+                leaves_path.len() == 1
+                    && (self.merkle_proof.proof().is_empty()
+                        || leaves_path[0].iter().all(|height| left.get_bit(*height)))
+            }
+            (None, Some((right, _))) => {
+                leaves_path.len() == 1
+                    && (self.merkle_proof.proof().is_empty()
+                        || leaves_path[0].iter().all(|height| !right.get_bit(*height)))
+            }
+            (None, None) => leaves_path.is_empty(),
+        }
+    }
+}
+
+/// Merkle proof that a list of leaves is every leaf of a tree in a key range
+///
+/// The proof opens the leaves of the range with the existing leaves around
+/// it, the greatest key before the start and the least key from the end, and
+/// shows no leaf of the tree lies between them.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RangeProof<K, V> {
+    left: Option<(K, V)>,
+    leaves: Vec<(K, V)>,
+    right: Option<(K, V)>,
+    merkle_proof: MerkleProof,
+}
+
+impl<K, V> RangeProof<K, V> {
+    /// left: the leaf before the range, if any
+    /// leaves: the leaves in the range, in ascending key order
+    /// right: the leaf after the range, if any
+    /// merkle_proof: the merkle proof of the left, range and right leaves
+    pub fn new(
+        left: Option<(K, V)>,
+        leaves: Vec<(K, V)>,
+        right: Option<(K, V)>,
+        merkle_proof: MerkleProof,
+    ) -> Self {
+        RangeProof {
+            left,
+            leaves,
+            right,
+            merkle_proof,
+        }
+    }
+
+    /// Destruct the structure, useful for serialization
+    #[allow(clippy::type_complexity)]
+    pub fn take(self) -> (Option<(K, V)>, Vec<(K, V)>, Option<(K, V)>, MerkleProof) {
+        let RangeProof {
+            left,
+            leaves,
+            right,
+            merkle_proof,
+        } = self;
+        (left, leaves, right, merkle_proof)
+    }
+
+    /// return the leaf before the range
+    pub fn left(&self) -> Option<&(K, V)> {
+        self.left.as_ref()
+    }
+
+    /// return the leaves in the range
+    pub fn leaves(&self) -> &[(K, V)] {
+        &self.leaves
+    }
+
+    /// return the leaf after the range
+    pub fn right(&self) -> Option<&(K, V)> {
+        self.right.as_ref()
+    }
+
+    /// return the merkle proof of the leaves
+    pub fn merkle_proof(&self) -> &MerkleProof {
+        &self.merkle_proof
+    }
+
+    /// Verify the leaves are all the leaves of the tree with root which keys
+    /// are in [start, end)
+    ///
+    /// return false when the neighbors are not existing leaves around the
+    /// range or when a leaf of the tree is missing
+    pub fn verify<H: Hasher + Default, const N: usize>(
+        &self,
+        root: &H256,
+        start: &K,
+        end: &K,
+    ) -> Result<bool>
+    where
+        K: Key<N>,
+        V: Value,
+    {
+        let leaves: Vec<(K, V)> = self
+            .left
+            .iter()
+            .chain(self.leaves.iter())
+            .chain(self.right.iter())
+            .cloned()
+            .collect();
+        if leaves.iter().any(|(_k, v)| v.is_zero()) {
+            return Ok(false);
+        }
+        let sorted = leaves.windows(2).all(|pair| *pair[0].0 < *pair[1].0);
+        let left_of_start = self.left.as_ref().is_none_or(|(k, _v)| **k < **start);
+        let in_range = self
+            .leaves
+            .iter()
+            .all(|(k, _v)| **start <= **k && **k < **end);
+        let right_of_end = self.right.as_ref().is_none_or(|(k, _v)| **k >= **end);
+        if !sorted || !left_of_start || !in_range || !right_of_end {
+            return Ok(false);
+        }
+        if leaves.is_empty() {
+            // nothing exists in an empty tree
+            return Ok(root.is_zero());
+        }
+        let keys: Vec<K> = leaves.iter().map(|(k, _v)| *k).collect();
+        if !self
+            .merkle_proof
+            .is_contiguous(&keys, self.left.is_none(), self.right.is_none())
+        {
+            return Ok(false);
+        }
+        let calculated_root = self
+            .merkle_proof
+            .clone()
+            .compute_root::<H, K, V, N>(leaves)?;
+        Ok(&calculated_root == root)
+    }
+}
+
+/// Merkle proof of the transition of a tree by a set of writes
+///
+/// The proof opens the leaves the writes touch before they are applied, a
+/// missing key with a zero value, and the existing leaves next to the missing
+/// keys to show no leaf of the tree lies between them. The new root is
+/// computed from the same merkle proof with the written values.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpdateProof<K, V> {
+    leaves: Vec<(K, V)>,
+    merkle_proof: MerkleProof,
+}
+
+impl<K, V> UpdateProof<K, V> {
+    /// leaves: the old leaves, in ascending key order
+    /// merkle_proof: the merkle proof of the old leaves
+    pub fn new(leaves: Vec<(K, V)>, merkle_proof: MerkleProof) -> Self {
+        UpdateProof {
+            leaves,
+            merkle_proof,
+        }
+    }
+
+    /// Destruct the structure, useful for serialization
+    pub fn take(self) -> (Vec<(K, V)>, MerkleProof) {
+        let UpdateProof {
+            leaves,
+            merkle_proof,
+        } = self;
+        (leaves, merkle_proof)
+    }
+
+    /// return the leaves before the writes
+    pub fn leaves(&self) -> &[(K, V)] {
+        &self.leaves
+    }
+
+    /// return the merkle proof of the leaves
+    pub fn merkle_proof(&self) -> &MerkleProof {
+        &self.merkle_proof
+    }
+
+    /// Verify the writes, applied in order, turn the tree with old_root into
+    /// the tree with new_root
+    ///
+    /// return false when a written key isn't opened, when a key is missing
+    /// without its neighbors or when a root doesn't match
+    pub fn verify<H: Hasher + Default, const N: usize>(
+        &self,
+        old_root: &H256,
+        new_root: &H256,
+        writes: &[(K, V)],
+    ) -> Result<bool>
+    where
+        K: Key<N>,
+        V: Value,
+    {
+        let sorted = self.leaves.windows(2).all(|pair| *pair[0].0 < *pair[1].0);
+        if self.leaves.is_empty() || !sorted {
+            return Ok(false);
+        }
+        // the gaps around missing keys up to the existing leaves next to them
+        let len = self.leaves.len();
+        let mut must_be_empty = vec![false; len + 1];
+        let mut start = 0;
+        for i in 0..=len {
+            if i == len || !self.leaves[i].1.is_zero() {
+                if i > start {
+                    must_be_empty[start..=i]
+                        .iter_mut()
+                        .for_each(|gap| *gap = true);
+                }
+                start = i + 1;
+            }
+        }
+        let keys: Vec<K> = self.leaves.iter().map(|(k, _v)| *k).collect();
+        if !self
+            .merkle_proof
+            .has_empty_gaps::<K, _, N>(&keys, |gap| must_be_empty[gap])
+        {
+            return Ok(false);
+        }
+
+        let mut leaves = self.leaves.clone();
+        for (key, value) in writes {
+            match keys.binary_search_by_key(&**key, |k| **k) {
+                Ok(i) => leaves[i].1 = value.clone(),
+                Err(_) => return Ok(false),
+            }
+        }
+        let old = self
+            .merkle_proof
+            .clone()
+            .compute_root::<H, K, V, N>(self.leaves.clone())?;
+        let new = self
+            .merkle_proof
+            .clone()
+            .compute_root::<H, K, V, N>(leaves)?;
+        Ok(&old == old_root && &new == new_root)
+    }
+}
+
 /// An structure optimized for verify merkle proof
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        try_from = "CompiledMerkleProofEncoding",
+        into = "CompiledMerkleProofEncoding"
+    )
+)]
 pub struct CompiledMerkleProof(pub Vec<u8>);
 
 impl CompiledMerkleProof {
+    /// Check the program is well formed for a tree with N bytes keys
+    ///
+    /// The codes must be valid and complete, the heights must be below the
+    /// tree height 8 * N and the program must leave a single node on the stack
+    pub fn check<const N: usize>(&self) -> Result<()> {
+        check_program(&self.0, 8 * N).map(|_leaves_count| ())
+    }
+
+    /// Decode the borsh encoding of a program for a tree with N bytes keys
+    ///
+    /// The decoded program is checked for the tree, see `check`, while
+    /// `BorshDeserialize` only bounds the heights by the longest keys.
+    /// return CorruptedProof error if the bytes are not a program encoding
+    #[cfg(feature = "borsh")]
+    pub fn decode<const N: usize>(bytes: &[u8]) -> Result<Self> {
+        let proof: Self = borsh::from_slice(bytes).map_err(|_| Error::CorruptedProof)?;
+        proof.check::<N>()?;
+        Ok(proof)
+    }
+
+    /// Check the program is well formed for a tree with N bytes keys, see
+    /// `check`, and reads leaves_count leaves
+    ///
+    /// return IncorrectNumberOfLeaves error if the program reads another
+    /// number of leaves
+    pub fn validate<const N: usize>(&self, leaves_count: usize) -> Result<()> {
+        let expected = check_program(&self.0, 8 * N)?;
+        if expected != leaves_count {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected,
+                actual: leaves_count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decode the program into its ops
+    ///
+    /// Only the codes and the size of their data are checked, so a corrupted
+    /// program can be inspected. return InvalidCode error for an unknown code
+    /// and CorruptedProof error for a truncated one
+    pub fn disassemble(&self) -> Result<Vec<Op>> {
+        Ops::new(&self.0).collect()
+    }
+
+    /// Encode the ops into a program
+    pub fn assemble(ops: &[Op]) -> Self {
+        let mut program = Vec::with_capacity(ops.iter().map(Op::encoded_len).sum());
+        for op in ops {
+            op.encode(&mut program);
+        }
+        CompiledMerkleProof(program)
+    }
+
     pub fn compute_root<H: Hasher + Default, K, V, const N: usize>(
         &self,
         mut leaves: Vec<(K, V)>,
@@ -327,89 +1010,9 @@ impl CompiledMerkleProof {
         V: Value,
     {
         leaves.sort_unstable_by_key(|(k, _v)| **k);
-        let mut program_index = 0;
-        let mut leave_index = 0;
-        let mut stack = Vec::new();
-        while program_index < self.0.len() {
-            let code = self.0[program_index];
-            program_index += 1;
-            match code {
-                // L
-                0x4C => {
-                    if leave_index >= leaves.len() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    let (k, v) = leaves[leave_index].clone();
-                    stack.push((*k, hash_leaf::<H, K, V, N>(&k, &v)));
-                    leave_index += 1;
-                }
-                // P
-                0x50 => {
-                    if stack.is_empty() {
-                        return Err(Error::CorruptedStack);
-                    }
-                    if program_index + 40 > self.0.len() {
-                        return Err(Error::CorruptedProof);
-                    }
-                    let height: [u8; 8] = self.0[program_index..program_index + 8]
-                        .try_into()
-                        .expect("8 bytes should fit in an 8 byte array");
-                    let height = u64::from_be_bytes(height) as usize;
-                    program_index += 8;
-                    let mut data = [0u8; 32];
-                    data.copy_from_slice(&self.0[program_index..program_index + 32]);
-                    program_index += 32;
-                    let proof = H256::from(data);
-                    let (key, value) = stack.pop().unwrap();
-                    let parent_key = key.parent_path(height);
-                    let parent = if key.get_bit(height) {
-                        merge::<H>(&proof, &value)
-                    } else {
-                        merge::<H>(&value, &proof)
-                    };
-                    stack.push((parent_key, parent));
-                }
-                // H
-                0x48 => {
-                    if stack.len() < 2 {
-                        return Err(Error::CorruptedStack);
-                    }
-                    if program_index >= self.0.len() {
-                        return Err(Error::CorruptedProof);
-                    }
-                    let height: [u8; 8] = self.0[program_index..program_index + 8]
-                        .try_into()
-                        .expect("8 bytes should fit in an 8 byte array");
-                    let height = u64::from_be_bytes(height) as usize;
-                    program_index += 8;
-                    let (key_b, value_b) = stack.pop().unwrap();
-                    let (key_a, value_a) = stack.pop().unwrap();
-                    let parent_key_a = key_a.copy_bits(height..);
-                    let parent_key_b = key_b.copy_bits(height..);
-                    let a_set = key_a.get_bit(height);
-                    let b_set = key_b.get_bit(height);
-                    let mut sibling_key_a = parent_key_a;
-                    if !a_set {
-                        sibling_key_a.set_bit(height);
-                    }
-                    // Test if a and b are siblings
-                    if !(sibling_key_a == parent_key_b && (a_set ^ b_set)) {
-                        return Err(Error::NonSiblings);
-                    }
-                    let parent = if key_a.get_bit(height) {
-                        merge::<H>(&value_b, &value_a)
-                    } else {
-                        merge::<H>(&value_a, &value_b)
-                    };
-                    stack.push((parent_key_a, parent));
-                }
-                _ => return Err(Error::InvalidCode(code)),
-            }
-        }
-        if stack.len() != 1 {
-            return Err(Error::CorruptedStack);
-        }
-        Ok(stack[0].1)
+        // each node on the stack holds at least one leaf
+        let mut stack = vec![(InternalKey::zero(), H256::zero()); leaves.len()];
+        run_program::<H, K, V, N>(&self.0, &leaves, &mut stack)
     }
 
     pub fn verify<H: Hasher + Default, K, V, const N: usize>(
@@ -425,3 +1028,326 @@ impl CompiledMerkleProof {
         Ok(&calculated_root == root)
     }
 }
+
+/// Compute the root of a compiled proof program without allocating
+///
+/// leaves: sorted by key in strictly ascending order, borrowed from the caller
+///
+/// The nodes are kept on a stack of D entries, one per leaf is always enough.
+/// return UnsortedKeys error when the leaves aren't sorted, StackOverflow
+/// error when the program needs more than D entries and
+/// IncorrectNumberOfLeaves error when it doesn't read every leaf
+pub fn compute_compiled_root<H, K, V, const N: usize, const D: usize>(
+    program: &[u8],
+    leaves: &[(K, V)],
+) -> Result<H256>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+{
+    if !leaves.windows(2).all(|pair| *pair[0].0 < *pair[1].0) {
+        return Err(Error::UnsortedKeys);
+    }
+    let mut stack = [(InternalKey::zero(), H256::zero()); D];
+    run_program::<H, K, V, N>(program, leaves, &mut stack)
+}
+
+/// Verify a compiled proof program against the root without allocating, see
+/// `compute_compiled_root`
+pub fn verify_compiled<H, K, V, const N: usize, const D: usize>(
+    program: &[u8],
+    root: &H256,
+    leaves: &[(K, V)],
+) -> Result<bool>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+{
+    let calculated_root = compute_compiled_root::<H, K, V, N, D>(program, leaves)?;
+    Ok(&calculated_root == root)
+}
+
+/// Run the program over the sorted leaves with the nodes on the stack
+fn run_program<H, K, V, const N: usize>(
+    program: &[u8],
+    leaves: &[(K, V)],
+    stack: &mut [(InternalKey<N>, H256)],
+) -> Result<H256>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+{
+    let mut leave_index = 0;
+    let mut stack_len = 0;
+    for op in Ops::new(program) {
+        match op? {
+            Op::Leaf => {
+                if leave_index >= leaves.len() {
+                    return Err(Error::CorruptedStack);
+                }
+                if stack_len == stack.len() {
+                    return Err(Error::StackOverflow(stack.len()));
+                }
+                let (k, v) = &leaves[leave_index];
+                stack[stack_len] = (**k, hash_leaf::<H, K, V, N>(k, v));
+                stack_len += 1;
+                leave_index += 1;
+            }
+            Op::Proof(height, proof) => {
+                if stack_len == 0 {
+                    return Err(Error::CorruptedStack);
+                }
+                check_height(height, 8 * N)?;
+                let (key, value) = stack[stack_len - 1];
+                let parent_key = key.parent_path(height);
+                let parent = if key.get_bit(height) {
+                    merge::<H>(height, &proof, &value)
+                } else {
+                    merge::<H>(height, &value, &proof)
+                };
+                stack[stack_len - 1] = (parent_key, parent);
+            }
+            Op::Merge(height) => {
+                if stack_len < 2 {
+                    return Err(Error::CorruptedStack);
+                }
+                check_height(height, 8 * N)?;
+                let (key_b, value_b) = stack[stack_len - 1];
+                let (key_a, value_a) = stack[stack_len - 2];
+                stack_len -= 1;
+                let parent_key_a = key_a.copy_bits(height..);
+                let parent_key_b = key_b.copy_bits(height..);
+                let a_set = key_a.get_bit(height);
+                let b_set = key_b.get_bit(height);
+                let mut sibling_key_a = parent_key_a;
+                if !a_set {
+                    sibling_key_a.set_bit(height);
+                }
+                // Test if a and b are siblings
+                if !(sibling_key_a == parent_key_b && (a_set ^ b_set)) {
+                    return Err(Error::NonSiblings);
+                }
+                let parent = if key_a.get_bit(height) {
+                    merge::<H>(height, &value_b, &value_a)
+                } else {
+                    merge::<H>(height, &value_a, &value_b)
+                };
+                stack[stack_len - 1] = (parent_key_a, parent);
+            }
+        }
+    }
+    if stack_len != 1 {
+        return Err(Error::CorruptedStack);
+    }
+    // the root doesn't commit to the leaves the program doesn't read
+    if leave_index != leaves.len() {
+        return Err(Error::IncorrectNumberOfLeaves {
+            expected: leave_index,
+            actual: leaves.len(),
+        });
+    }
+    Ok(stack[0].1)
+}
+
+/// An op of a compiled proof program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// L: push the hash of the next leaf
+    Leaf,
+    /// P: merge the top node at height with a node of the proof
+    Proof(usize, H256),
+    /// H: merge the two top nodes, which are siblings at height
+    Merge(usize),
+}
+
+impl Op {
+    /// return the number of bytes of the op in a program
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Op::Leaf => 1,
+            Op::Proof(..) => 41,
+            Op::Merge(_) => 9,
+        }
+    }
+
+    fn encode(&self, program: &mut Vec<u8>) {
+        match self {
+            Op::Leaf => program.push(0x4C),
+            Op::Proof(height, proof) => {
+                program.push(0x50);
+                program.extend_from_slice(&(*height as u64).to_be_bytes());
+                program.extend_from_slice(proof.as_slice());
+            }
+            Op::Merge(height) => {
+                program.push(0x48);
+                program.extend_from_slice(&(*height as u64).to_be_bytes());
+            }
+        }
+    }
+}
+
+impl core::fmt::Display for Op {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Op::Leaf => write!(f, "L"),
+            Op::Proof(height, proof) => {
+                write!(f, "P {} ", height)?;
+                for byte in proof.as_slice() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Op::Merge(height) => write!(f, "H {}", height),
+        }
+    }
+}
+
+/// Iterator decoding the ops of a program, which ends after an error
+struct Ops<'a> {
+    program: &'a [u8],
+}
+
+impl<'a> Ops<'a> {
+    fn new(program: &'a [u8]) -> Self {
+        Ops { program }
+    }
+
+    fn next_op(&mut self) -> Result<Op> {
+        let (code, data) = self.program.split_first().expect("program isn't empty");
+        let data_len = match code {
+            // L
+            0x4C => 0,
+            // P
+            0x50 => 40,
+            // H
+            0x48 => 8,
+            _ => return Err(Error::InvalidCode(*code)),
+        };
+        if data.len() < data_len {
+            return Err(Error::CorruptedProof);
+        }
+        let (data, rest) = data.split_at(data_len);
+        self.program = rest;
+        if data_len == 0 {
+            return Ok(Op::Leaf);
+        }
+        let height: [u8; 8] = data[..8]
+            .try_into()
+            .expect("8 bytes should fit in an 8 byte array");
+        let height =
+            usize::try_from(u64::from_be_bytes(height)).map_err(|_| Error::CorruptedProof)?;
+        if data_len == 8 {
+            return Ok(Op::Merge(height));
+        }
+        let proof: [u8; 32] = data[8..]
+            .try_into()
+            .expect("32 bytes should fit in a 32 byte array");
+        Ok(Op::Proof(height, proof.into()))
+    }
+}
+
+impl<'a> Iterator for Ops<'a> {
+    type Item = Result<Op>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.program.is_empty() {
+            return None;
+        }
+        let op = self.next_op();
+        if op.is_err() {
+            self.program = &[];
+        }
+        Some(op)
+    }
+}
+
+/// the heights of a program must be below the tree height
+fn check_height(height: usize, tree_height: usize) -> Result<()> {
+    if height >= tree_height {
+        return Err(Error::CorruptedProof);
+    }
+    Ok(())
+}
+
+/// Check the program is well formed, return the number of leaves it reads
+fn check_program(program: &[u8], tree_height: usize) -> Result<usize> {
+    let mut stack_len = 0;
+    let mut leaves_count = 0;
+    for op in Ops::new(program) {
+        let op = op?;
+        let pops = match op {
+            Op::Leaf => {
+                leaves_count += 1;
+                0
+            }
+            Op::Proof(height, _) => {
+                check_height(height, tree_height)?;
+                1
+            }
+            Op::Merge(height) => {
+                check_height(height, tree_height)?;
+                2
+            }
+        };
+        if stack_len < pops {
+            return Err(Error::CorruptedStack);
+        }
+        stack_len = stack_len - pops + 1;
+    }
+    if stack_len != 1 {
+        return Err(Error::CorruptedStack);
+    }
+    Ok(leaves_count)
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for CompiledMerkleProof {
+    fn serialize<W: Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        BorshSerialize::serialize(&PROOF_ENCODING_VERSION, writer)?;
+        BorshSerialize::serialize(&self.0, writer)
+    }
+}
+
+/// The heights are only bounded by the longest keys, see
+/// `CompiledMerkleProof::decode`
+#[cfg(feature = "borsh")]
+impl BorshDeserialize for CompiledMerkleProof {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        check_version(BorshDeserialize::deserialize_reader(reader)?)?;
+        let program: Vec<u8> = BorshDeserialize::deserialize_reader(reader)?;
+        check_program(&program, MAX_TREE_HEIGHT)
+            .map_err(|err| IoError::new(ErrorKind::InvalidData, err.to_string()))?;
+        Ok(CompiledMerkleProof(program))
+    }
+}
+
+/// Versioned serde encoding of CompiledMerkleProof
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+enum CompiledMerkleProofEncoding {
+    V1(Vec<u8>),
+}
+
+#[cfg(feature = "serde")]
+impl From<CompiledMerkleProof> for CompiledMerkleProofEncoding {
+    fn from(compiled_proof: CompiledMerkleProof) -> Self {
+        CompiledMerkleProofEncoding::V1(compiled_proof.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<CompiledMerkleProofEncoding> for CompiledMerkleProof {
+    type Error = Error;
+
+    fn try_from(encoding: CompiledMerkleProofEncoding) -> Result<Self> {
+        match encoding {
+            CompiledMerkleProofEncoding::V1(program) => {
+                check_program(&program, MAX_TREE_HEIGHT)?;
+                Ok(CompiledMerkleProof(program))
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::H256;
+
+/// Element of the scalar field of BN254
+///
+/// The limbs are little endian and in Montgomery form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fr(pub(super) [u64; 4]);
+
+/// The field order
+/// 21888242871839275222246405745257275088548364400416034343698204186575808495617
+const MODULUS: [u64; 4] = [
+    0x43e1f593f0000001,
+    0x2833e84879b97091,
+    0xb85045b68181585d,
+    0x30644e72e131a029,
+];
+
+/// -MODULUS^-1 mod 2^64
+const INV: u64 = 0xc2e1f593efffffff;
+
+/// 2^512 mod MODULUS, converts an integer into Montgomery form
+const R2: Fr = Fr([
+    0x1bb8e645ae216da7,
+    0x53fe3ab1e35c59e3,
+    0x8c49833d53bb8085,
+    0x0216d0b17f4e44a5,
+]);
+
+impl Fr {
+    pub const fn zero() -> Self {
+        Fr([0; 4])
+    }
+
+    /// Read a field element from its 32 bytes big endian encoding
+    ///
+    /// return None if the integer isn't below the field order
+    pub fn from_h256(h: &H256) -> Option<Self> {
+        let bytes = h.as_slice();
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut be = [0u8; 8];
+            be.copy_from_slice(&bytes[32 - 8 * (i + 1)..32 - 8 * i]);
+            *limb = u64::from_be_bytes(be);
+        }
+        if !is_below_modulus(&limbs) {
+            return None;
+        }
+        Some(Fr(limbs).mul(&R2))
+    }
+
+    /// Write the field element as 32 bytes big endian
+    pub fn to_h256(&self) -> H256 {
+        let limbs = self.mul(&Fr([1, 0, 0, 0])).0;
+        let mut bytes = [0u8; 32];
+        for (i, limb) in limbs.iter().enumerate() {
+            bytes[32 - 8 * (i + 1)..32 - 8 * i].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes.into()
+    }
+
+    pub(super) fn add(&self, rhs: &Fr) -> Fr {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c) = adc(self.0[i], rhs.0[i], carry);
+            *limb = sum;
+            carry = c;
+        }
+        // the modulus is below 2^254, the sum doesn't overflow
+        reduce(limbs)
+    }
+
+    /// Montgomery multiplication
+    #[allow(clippy::needless_range_loop)]
+    pub(super) fn mul(&self, rhs: &Fr) -> Fr {
+        let mut t = [0u64; 6];
+        for i in 0..4 {
+            let mut carry = 0;
+            for j in 0..4 {
+                let (lo, hi) = mac(t[j], self.0[j], rhs.0[i], carry);
+                t[j] = lo;
+                carry = hi;
+            }
+            let (sum, c) = adc(t[4], carry, 0);
+            t[4] = sum;
+            t[5] = c;
+
+            let m = t[0].wrapping_mul(INV);
+            let (_, mut carry) = mac(t[0], m, MODULUS[0], 0);
+            for j in 1..4 {
+                let (lo, hi) = mac(t[j], m, MODULUS[j], carry);
+                t[j - 1] = lo;
+                carry = hi;
+            }
+            let (sum, c) = adc(t[4], carry, 0);
+            t[3] = sum;
+            t[4] = t[5] + c;
+        }
+        reduce([t[0], t[1], t[2], t[3]])
+    }
+
+    pub(super) fn pow5(&self) -> Fr {
+        let square = self.mul(self);
+        square.mul(&square).mul(self)
+    }
+}
+
+impl From<u64> for Fr {
+    fn from(v: u64) -> Self {
+        Fr([v, 0, 0, 0]).mul(&R2)
+    }
+}
+
+fn is_below_modulus(limbs: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if limbs[i] != MODULUS[i] {
+            return limbs[i] < MODULUS[i];
+        }
+    }
+    false
+}
+
+/// Subtract the modulus from a value below twice the modulus
+fn reduce(limbs: [u64; 4]) -> Fr {
+    if is_below_modulus(&limbs) {
+        return Fr(limbs);
+    }
+    let mut reduced = [0u64; 4];
+    let mut borrow = 0;
+    for (i, limb) in reduced.iter_mut().enumerate() {
+        let (diff, b1) = limbs[i].overflowing_sub(MODULUS[i]);
+        let (diff, b2) = diff.overflowing_sub(borrow);
+        *limb = diff;
+        borrow = (b1 | b2) as u64;
+    }
+    Fr(reduced)
+}
+
+/// a + b + carry, return the sum and the carry
+fn adc(a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let sum = a as u128 + b as u128 + carry as u128;
+    (sum as u64, (sum >> 64) as u64)
+}
+
+/// a + b * c + carry, return the low and high words
+fn mac(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let sum = a as u128 + (b as u128) * (c as u128) + carry as u128;
+    (sum as u64, (sum >> 64) as u64)
+}
@@ -0,0 +1,105 @@
+//! Poseidon hasher over the BN254 scalar field for trees proven in circuits
+//!
+//! The permutation has width 3 and the constants of circomlib, a branch hash
+//! is `Poseidon(lhs, rhs)` of circomlib with the nodes read as field
+//! elements by `Fr::from_h256`. Other inputs, like the leaves, are absorbed by
+//! a sponge in chunks of 31 bytes, see `PoseidonHasher`.
+
+mod constants;
+mod field;
+
+pub use field::Fr;
+
+use crate::{traits::Hasher, vec::Vec, H256};
+use constants::{MDS, ROUND_CONSTANTS};
+
+const WIDTH: usize = 3;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+/// Bytes of the chunks absorbed by the sponge, 31 bytes are below the field order
+const CHUNK_SIZE: usize = 31;
+
+/// Hash two field elements like `Poseidon(2)` of circomlib
+pub fn poseidon(lhs: &Fr, rhs: &Fr) -> Fr {
+    let mut state = [Fr::zero(), *lhs, *rhs];
+    permute(&mut state);
+    state[0]
+}
+
+/// Poseidon hasher
+///
+/// The 64 bytes made of two field elements, the merged branches, are hashed
+/// by `poseidon`. Any other input is absorbed 2 chunks of 31 big endian bytes
+/// at a time with the capacity set to its length plus one, which separates it
+/// from the branches.
+///
+/// A circuit computes the branches with a single permutation when the tree
+/// isn't wrapped in `merge::DomainSeparated`.
+#[derive(Default)]
+pub struct PoseidonHasher(Vec<u8>);
+
+impl Hasher for PoseidonHasher {
+    fn write_bytes(&mut self, h: &[u8]) {
+        self.0.extend_from_slice(h);
+    }
+
+    fn finish(self) -> H256 {
+        if self.0.len() == 64 {
+            let (lhs, rhs) = self.0.split_at(32);
+            if let (Some(lhs), Some(rhs)) = (read_element(lhs), read_element(rhs)) {
+                return poseidon(&lhs, &rhs).to_h256();
+            }
+        }
+        sponge(&self.0).to_h256()
+    }
+}
+
+/// Read the field element of 32 big endian bytes
+fn read_element(bytes: &[u8]) -> Option<Fr> {
+    let mut h = [0u8; 32];
+    h.copy_from_slice(bytes);
+    Fr::from_h256(&h.into())
+}
+
+fn sponge(bytes: &[u8]) -> Fr {
+    let mut state = [Fr::from(bytes.len() as u64 + 1), Fr::zero(), Fr::zero()];
+    let mut chunks = bytes.chunks(CHUNK_SIZE).map(chunk_element).peekable();
+    loop {
+        state[1] = state[1].add(&chunks.next().unwrap_or_else(Fr::zero));
+        state[2] = state[2].add(&chunks.next().unwrap_or_else(Fr::zero));
+        permute(&mut state);
+        if chunks.peek().is_none() {
+            return state[0];
+        }
+    }
+}
+
+fn chunk_element(chunk: &[u8]) -> Fr {
+    let mut bytes = [0u8; 32];
+    bytes[32 - chunk.len()..].copy_from_slice(chunk);
+    Fr::from_h256(&bytes.into()).expect("31 bytes are below the field order")
+}
+
+fn permute(state: &mut [Fr; WIDTH]) {
+    let half_full_rounds = FULL_ROUNDS / 2;
+    for round in 0..FULL_ROUNDS + PARTIAL_ROUNDS {
+        for (i, element) in state.iter_mut().enumerate() {
+            *element = element.add(&ROUND_CONSTANTS[round * WIDTH + i]);
+        }
+        let is_full = round < half_full_rounds || round >= half_full_rounds + PARTIAL_ROUNDS;
+        if is_full {
+            for element in state.iter_mut() {
+                *element = element.pow5();
+            }
+        } else {
+            state[0] = state[0].pow5();
+        }
+        let mut mixed = [Fr::zero(); WIDTH];
+        for (row, element) in MDS.iter().zip(mixed.iter_mut()) {
+            for (m, s) in row.iter().zip(state.iter()) {
+                *element = element.add(&m.mul(s));
+            }
+        }
+        *state = mixed;
+    }
+}
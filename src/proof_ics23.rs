@@ -1,22 +1,36 @@
-use ics23::{ExistenceProof, HashOp, InnerOp, InnerSpec, LeafOp, LengthOp, ProofSpec};
+use ics23::{
+    batch_entry, commitment_proof, CommitmentProof, ExistenceProof, HashOp, HostFunctionsProvider,
+    InnerOp, InnerSpec, LeafOp, LengthOp, NonExistenceProof, ProofSpec,
+};
 
 use crate::collections::VecDeque;
 use crate::error::{Error, Result};
-use crate::{Key, MerkleProof, H256, TREE_HEIGHT, traits::Value};
+use crate::merge::{BRANCH_PREFIX, LEAF_PREFIX};
+use crate::vec::Vec;
+use crate::{
+    traits::{Hasher, Value},
+    Key, MerkleProof, H256,
+};
 
-pub fn convert<K, V, const N: usize>(
+/// Convert the merkle proof of a single key into an ICS23 existence proof of
+/// a tree hashed by H
+pub fn convert<H, K, V, const N: usize>(
     merkle_proof: MerkleProof,
     key: &K,
     value: &V,
-    hash_op: HashOp,
 ) -> Result<ExistenceProof>
 where
+    H: Hasher,
     K: Key<N>,
     V: Value,
 {
+    let hash_op = H::hash_op();
+    let domain_separated = H::is_domain_separated();
+    let prehash_key = prehash_key_op::<K, N>()?;
+    merkle_proof.check::<N>()?;
     let (leaves_path, proof) = merkle_proof.take();
     let mut merge_heights: VecDeque<_> = leaves_path
-        .get(0)
+        .first()
         .expect("The heights should exist")
         .clone()
         .into();
@@ -25,15 +39,13 @@ where
     let mut height = 0;
     let mut path = Vec::new();
     while !proof.is_empty() {
-        if height == TREE_HEIGHT {
-            if !proof.is_empty() {
-                return Err(Error::CorruptedProof);
-            }
-            break;
+        // the proof can't be higher than the tree
+        if height >= 8 * N {
+            return Err(Error::CorruptedProof);
         }
 
         // check the height is valid
-        let merge_height = merge_heights.front().map(|h| *h as usize).unwrap_or(height);
+        let merge_height = merge_heights.front().copied().unwrap_or(height);
         if height != merge_height {
             // skip the heights
             height = merge_height;
@@ -42,11 +54,20 @@ where
 
         // get a proof
         let (sibling, sibling_height) = proof.pop_front().expect("no proof");
-        if height < sibling_height as usize {
+        if height < sibling_height {
             // skip heights
-            height = sibling_height as usize;
+            height = sibling_height;
         }
-        let inner_op = get_inner_op(hash_op, &sibling, cur_key.get_bit(height));
+        if height >= 8 * N {
+            return Err(Error::CorruptedProof);
+        }
+        let inner_op = get_inner_op(
+            hash_op,
+            domain_separated,
+            &sibling,
+            height,
+            cur_key.get_bit(height),
+        );
         path.push(inner_op);
 
         merge_heights.pop_front();
@@ -57,38 +78,224 @@ where
     Ok(ExistenceProof {
         key: key.to_vec(),
         value: value.as_slice().to_vec(),
-        leaf: Some(get_leaf_op(hash_op)),
+        leaf: Some(get_leaf_op(
+            hash_op,
+            domain_separated,
+            prehash_key,
+            V::is_prehashed(),
+        )),
         path,
     })
 }
 
-pub fn get_spec(hash_op: HashOp) -> ProofSpec {
+/// Get the proof spec of a tree with N bytes keys, the depth of the proofs is
+/// at most the tree height 8 * N, which is checked by `verify_membership`
+/// and `verify_non_membership` but not by the ics23 verifiers
+pub fn get_spec<const N: usize>(hash_op: HashOp) -> ProofSpec {
+    proof_spec::<N>(hash_op, false, None, false)
+}
+
+/// Get the proof spec of a tree with N bytes keys and domain separated
+/// hashing, see `merge::DomainSeparated`
+pub fn get_domain_separated_spec<const N: usize>(hash_op: HashOp) -> ProofSpec {
+    proof_spec::<N>(hash_op, true, None, false)
+}
+
+/// Get the proof spec of a tree with prehashed keys, like
+/// `hashed_key::HashedKey`, whose internal keys are the prehash_key hash of
+/// the keys, which are compared by their hashes
+///
+/// return UnsupportedPrehash error if prehash_key is NoHash
+pub fn get_prehashed_key_spec<const N: usize>(
+    hash_op: HashOp,
+    prehash_key: HashOp,
+) -> Result<ProofSpec> {
+    if prehash_key == HashOp::NoHash {
+        return Err(Error::UnsupportedPrehash);
+    }
+    Ok(proof_spec::<N>(hash_op, false, Some(prehash_key), false))
+}
+
+/// Get the proof spec of a tree with prehashed keys and domain separated
+/// hashing
+///
+/// return UnsupportedPrehash error if prehash_key is NoHash
+pub fn get_domain_separated_prehashed_key_spec<const N: usize>(
+    hash_op: HashOp,
+    prehash_key: HashOp,
+) -> Result<ProofSpec> {
+    if prehash_key == HashOp::NoHash {
+        return Err(Error::UnsupportedPrehash);
+    }
+    Ok(proof_spec::<N>(hash_op, true, Some(prehash_key), false))
+}
+
+/// Get the proof spec of a tree hashed by H with K keys and V values, which
+/// covers the options of the other specs and prehashed values, see
+/// `traits::Value::is_prehashed`
+///
+/// return UnsupportedPrehash error if the keys are prehashed without an ICS23
+/// hash operation
+pub fn get_tree_spec<H, K, V, const N: usize>() -> Result<ProofSpec>
+where
+    H: Hasher,
+    K: Key<N>,
+    V: Value,
+{
+    Ok(proof_spec::<N>(
+        H::hash_op(),
+        H::is_domain_separated(),
+        prehash_key_op::<K, N>()?,
+        V::is_prehashed(),
+    ))
+}
+
+/// Get the ICS23 hash of the prehashed keys
+///
+/// return UnsupportedPrehash error if the keys are prehashed without one,
+/// their proofs couldn't be verified
+pub(crate) fn prehash_key_op<K: Key<N>, const N: usize>() -> Result<Option<HashOp>> {
+    match K::prehash_op() {
+        None if K::is_prehashed() => Err(Error::UnsupportedPrehash),
+        prehash_key => Ok(prehash_key),
+    }
+}
+
+/// Verify an ICS23 membership proof, and that it is not deeper than
+/// `spec.max_depth`, see `check_depth`
+pub fn verify_membership<HF: HostFunctionsProvider>(
+    proof: &CommitmentProof,
+    spec: &ProofSpec,
+    root: &[u8],
+    key: &[u8],
+    value: &[u8],
+) -> bool {
+    check_depth(proof, spec)
+        && ics23::verify_membership::<HF>(proof, spec, &root.to_vec(), key, value)
+}
+
+/// Verify an ICS23 non membership proof, and that its neighbors' proofs are
+/// not deeper than `spec.max_depth`, see `check_depth`
+pub fn verify_non_membership<HF: HostFunctionsProvider>(
+    proof: &CommitmentProof,
+    spec: &ProofSpec,
+    root: &[u8],
+    key: &[u8],
+) -> bool {
+    check_depth(proof, spec) && ics23::verify_non_membership::<HF>(proof, spec, &root.to_vec(), key)
+}
+
+/// Check that the existence proofs in a commitment proof are at most
+/// `spec.max_depth` deep
+///
+/// ics23 only checks the depth of the proofs when `spec.min_depth` is set,
+/// and the specs of this crate leave it to 0 since the proof of a tree with
+/// a single leaf has no inner op. A spec whose `min_depth` is set would
+/// reject those proofs.
+pub fn check_depth(proof: &CommitmentProof, spec: &ProofSpec) -> bool {
+    let decompressed;
+    let proof = if ics23::is_compressed(proof) {
+        match ics23::decompress(proof) {
+            Ok(proof) => {
+                decompressed = proof;
+                &decompressed
+            }
+            Err(_) => return false,
+        }
+    } else {
+        proof
+    };
+    let max_depth = spec.max_depth.max(0) as usize;
+    let exist_ok = |exist: &ExistenceProof| exist.path.len() <= max_depth;
+    let non_exist_ok =
+        |non_exist: &NonExistenceProof| non_exist.left.iter().chain(&non_exist.right).all(exist_ok);
+    match &proof.proof {
+        Some(commitment_proof::Proof::Exist(exist)) => exist_ok(exist),
+        Some(commitment_proof::Proof::Nonexist(non_exist)) => non_exist_ok(non_exist),
+        Some(commitment_proof::Proof::Batch(batch)) => {
+            batch.entries.iter().all(|entry| match &entry.proof {
+                Some(batch_entry::Proof::Exist(exist)) => exist_ok(exist),
+                Some(batch_entry::Proof::Nonexist(non_exist)) => non_exist_ok(non_exist),
+                None => true,
+            })
+        }
+        // decompressed above
+        Some(commitment_proof::Proof::Compressed(_)) => false,
+        None => true,
+    }
+}
+
+fn proof_spec<const N: usize>(
+    hash_op: HashOp,
+    domain_separated: bool,
+    prehash_key: Option<HashOp>,
+    prehash_value: bool,
+) -> ProofSpec {
     ProofSpec {
-        leaf_spec: Some(get_leaf_op(hash_op)),
-        inner_spec: Some(get_inner_spec(hash_op)),
-        max_depth: TREE_HEIGHT as i32,
+        leaf_spec: Some(get_leaf_op(
+            hash_op,
+            domain_separated,
+            prehash_key,
+            prehash_value,
+        )),
+        inner_spec: Some(get_inner_spec(hash_op, domain_separated)),
+        max_depth: (8 * N) as i32,
         min_depth: 0,
-        prehash_key_before_comparison: false,
+        prehash_key_before_comparison: prehash_key.is_some(),
     }
 }
 
-fn get_leaf_op(hash_op: HashOp) -> LeafOp {
+fn get_leaf_op(
+    hash_op: HashOp,
+    domain_separated: bool,
+    prehash_key: Option<HashOp>,
+    prehash_value: bool,
+) -> LeafOp {
+    let prefix = if domain_separated {
+        vec![LEAF_PREFIX]
+    } else {
+        H256::zero().as_slice().to_vec()
+    };
     LeafOp {
         hash: hash_op.into(),
-        prehash_key: HashOp::NoHash.into(),
-        prehash_value: HashOp::NoHash.into(),
+        prehash_key: prehash_key.unwrap_or(HashOp::NoHash).into(),
+        prehash_value: if prehash_value {
+            hash_op
+        } else {
+            HashOp::NoHash
+        }
+        .into(),
+        // no length prefix like `merge::hash_leaf`, see `traits::Value::is_prehashed`
         length: LengthOp::NoPrefix.into(),
-        prefix: H256::zero().as_slice().to_vec(),
+        prefix,
     }
 }
 
-fn get_inner_op(hash_op: HashOp, sibling: &H256, is_right_node: bool) -> InnerOp {
-    let node = sibling.as_slice().to_vec();
-    let (prefix, suffix) = if is_right_node {
-        (node, vec![])
+/// the prefix of the branches merged at height
+fn branch_prefix(domain_separated: bool, height: usize) -> Vec<u8> {
+    if domain_separated {
+        let mut prefix = vec![BRANCH_PREFIX];
+        prefix.extend_from_slice(&(height as u64).to_be_bytes());
+        prefix
     } else {
-        (vec![], node)
-    };
+        vec![]
+    }
+}
+
+fn get_inner_op(
+    hash_op: HashOp,
+    domain_separated: bool,
+    sibling: &H256,
+    height: usize,
+    is_right_node: bool,
+) -> InnerOp {
+    let mut prefix = branch_prefix(domain_separated, height);
+    let mut suffix = vec![];
+    if is_right_node {
+        prefix.extend_from_slice(sibling.as_slice());
+    } else {
+        suffix.extend_from_slice(sibling.as_slice());
+    }
     InnerOp {
         hash: hash_op.into(),
         prefix,
@@ -96,12 +303,14 @@ fn get_inner_op(hash_op: HashOp, sibling: &H256, is_right_node: bool) -> InnerOp
     }
 }
 
-fn get_inner_spec(hash_op: HashOp) -> InnerSpec {
+fn get_inner_spec(hash_op: HashOp, domain_separated: bool) -> InnerSpec {
+    // the domain separated prefix is the branch prefix and the 8 bytes height
+    let (min_prefix_length, max_prefix_length) = if domain_separated { (9, 9) } else { (0, 32) };
     InnerSpec {
         child_order: vec![0, 1],
         child_size: 32,
-        min_prefix_length: 0,
-        max_prefix_length: 32,
+        min_prefix_length,
+        max_prefix_length,
         empty_child: vec![],
         hash: hash_op.into(),
     }
@@ -0,0 +1,272 @@
+use crate::{
+    error::{Error, Result},
+    iter::Iter,
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::SparseMerkleTree,
+    vec::Vec,
+    Key, H256,
+};
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+use core::{iter::Peekable, ops::Bound};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A chunk of the sorted leaves of a tree
+///
+/// The merkle proof opens the last leaf of the previous chunk with the leaves
+/// of the chunk, showing no leaf of the tree lies between them. The first
+/// chunk has no previous leaf and shows no leaf lies before it, the last
+/// chunk shows no leaf lies after it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotChunk<K, V> {
+    index: u64,
+    previous: Option<(K, V)>,
+    leaves: Vec<(K, V)>,
+    is_last: bool,
+    merkle_proof: MerkleProof,
+}
+
+impl<K, V> SnapshotChunk<K, V> {
+    /// previous: the last leaf of the previous chunk, None for the first one
+    /// merkle_proof: opens the previous leaf and the leaves
+    pub fn new(
+        index: u64,
+        previous: Option<(K, V)>,
+        leaves: Vec<(K, V)>,
+        is_last: bool,
+        merkle_proof: MerkleProof,
+    ) -> Self {
+        SnapshotChunk {
+            index,
+            previous,
+            leaves,
+            is_last,
+            merkle_proof,
+        }
+    }
+
+    /// return the position of the chunk in the snapshot
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// return the last leaf of the previous chunk
+    pub fn previous(&self) -> Option<&(K, V)> {
+        self.previous.as_ref()
+    }
+
+    pub fn leaves(&self) -> &[(K, V)] {
+        &self.leaves
+    }
+
+    /// Check if no chunk follows this one
+    pub fn is_last(&self) -> bool {
+        self.is_last
+    }
+
+    pub fn merkle_proof(&self) -> &MerkleProof {
+        &self.merkle_proof
+    }
+
+    /// Verify the leaves are the next ones of the tree with root after the
+    /// previous leaf
+    pub fn verify<H: Hasher + Default, const N: usize>(&self, root: &H256) -> Result<bool>
+    where
+        K: Key<N>,
+        V: Value,
+    {
+        if self.leaves.is_empty() {
+            // the single chunk of an empty tree
+            return Ok(self.index == 0
+                && self.previous.is_none()
+                && self.is_last
+                && root.is_zero());
+        }
+        if self.index == 0 && self.previous.is_some() {
+            return Ok(false);
+        }
+        let leaves: Vec<(K, V)> = self
+            .previous
+            .iter()
+            .chain(self.leaves.iter())
+            .cloned()
+            .collect();
+        let sorted = leaves.windows(2).all(|pair| *pair[0].0 < *pair[1].0);
+        if !sorted || leaves.iter().any(|(_k, v)| v.is_zero()) {
+            return Ok(false);
+        }
+        let keys: Vec<K> = leaves.iter().map(|(k, _v)| *k).collect();
+        if !self
+            .merkle_proof
+            .is_contiguous(&keys, self.previous.is_none(), self.is_last)
+        {
+            return Ok(false);
+        }
+        self.merkle_proof.clone().verify::<H, K, V, N>(root, leaves)
+    }
+}
+
+/// Iterator over the chunks of the tree with a root
+///
+/// The leaves are read in ascending key order, a store error ends the
+/// iteration.
+pub struct Snapshot<'a, H, K, V, S, const N: usize>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    tree: &'a SparseMerkleTree<H, K, V, S, N>,
+    root: H256,
+    leaves: Peekable<Iter<'a, K, V, S, N>>,
+    chunk_size: usize,
+    index: u64,
+    previous: Option<(K, V)>,
+    done: bool,
+}
+
+impl<'a, H, K, V, S, const N: usize> Snapshot<'a, H, K, V, S, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    /// Split the leaves of the tree with root, which nodes must be in the
+    /// store, in chunks of chunk_size leaves, at least one
+    pub fn new(tree: &'a SparseMerkleTree<H, K, V, S, N>, root: &H256, chunk_size: usize) -> Self {
+        let leaves = Iter::new(tree.store(), root, Bound::Unbounded, Bound::Unbounded).peekable();
+        Snapshot {
+            tree,
+            root: *root,
+            leaves,
+            chunk_size: chunk_size.max(1),
+            index: 0,
+            previous: None,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<SnapshotChunk<K, V>> {
+        let mut leaves = Vec::with_capacity(self.chunk_size);
+        while leaves.len() < self.chunk_size {
+            match self.leaves.next() {
+                Some(leaf) => leaves.push(leaf?),
+                None => break,
+            }
+        }
+        let is_last = self.leaves.peek().is_none();
+        let merkle_proof = if leaves.is_empty() {
+            MerkleProof::new(Vec::new(), Vec::new())
+        } else {
+            let keys = self
+                .previous
+                .iter()
+                .chain(leaves.iter())
+                .map(|(k, _v)| *k)
+                .collect();
+            self.tree.merkle_proof_at(&self.root, keys)?
+        };
+        let previous = core::mem::replace(&mut self.previous, leaves.last().cloned());
+        let chunk = SnapshotChunk::new(self.index, previous, leaves, is_last, merkle_proof);
+        self.index += 1;
+        Ok(chunk)
+    }
+}
+
+impl<'a, H, K, V, S, const N: usize> Iterator for Snapshot<'a, H, K, V, S, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    type Item = Result<SnapshotChunk<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.next_chunk();
+        self.done = chunk.as_ref().map_or(true, |chunk| chunk.is_last);
+        Some(chunk)
+    }
+}
+
+/// Rebuild a tree from the chunks of its snapshot
+///
+/// Each chunk is verified against the root before its leaves are written to
+/// the store, the chunks must arrive in order.
+pub struct SnapshotRestorer<H, K, V, S, const N: usize>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    root: H256,
+    tree: SparseMerkleTree<H, K, V, S, N>,
+    previous: Option<(K, V)>,
+    index: u64,
+    complete: bool,
+}
+
+impl<H, K, V, S, const N: usize> SnapshotRestorer<H, K, V, S, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    /// Restore the tree with root into the store, which should be empty
+    pub fn new(root: H256, store: S) -> Self {
+        SnapshotRestorer {
+            root,
+            tree: SparseMerkleTree::new(H256::zero(), store),
+            previous: None,
+            index: 0,
+            complete: false,
+        }
+    }
+
+    /// Verify the next chunk and write its leaves
+    ///
+    /// return InvalidChunk error if the chunk isn't the next one of the tree
+    pub fn restore(&mut self, chunk: SnapshotChunk<K, V>) -> Result<()> {
+        let invalid = Error::InvalidChunk(chunk.index);
+        if self.complete || chunk.index != self.index || chunk.previous != self.previous {
+            return Err(invalid);
+        }
+        if !chunk.verify::<H, N>(&self.root).unwrap_or(false) {
+            return Err(invalid);
+        }
+        self.previous = chunk.leaves.last().cloned();
+        self.complete = chunk.is_last;
+        self.index += 1;
+        if !chunk.leaves.is_empty() {
+            self.tree.update_all(chunk.leaves)?;
+        }
+        Ok(())
+    }
+
+    /// Check if the last chunk was restored
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// return the restored tree
+    ///
+    /// return IncompleteSnapshot error if chunks are missing or the tree
+    /// doesn't have the root
+    pub fn finish(self) -> Result<SparseMerkleTree<H, K, V, S, N>> {
+        if !self.complete || self.tree.root() != &self.root {
+            return Err(Error::IncompleteSnapshot);
+        }
+        Ok(self.tree)
+    }
+}
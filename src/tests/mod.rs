@@ -2,8 +2,19 @@ mod padded_key;
 
 use super::*;
 use crate::{
-    blake2b::Blake2bHasher, default_store::DefaultStore, error::Error, sha256::Sha256Hasher,
-    MerkleProof, SparseMerkleTree,
+    blake2b::Blake2bHasher,
+    blake2s::Blake2sHasher,
+    default_store::DefaultStore,
+    diff::Change,
+    error::Error,
+    file_store::FileStore,
+    merge::DomainSeparated,
+    merkle_proof::{compute_compiled_root, verify_compiled, CompiledMerkleProof, Op},
+    sha256::Sha256Hasher,
+    snapshot::{SnapshotChunk, SnapshotRestorer},
+    traits::Hasher,
+    versioned_store::{gc, VersionedStore},
+    MerkleProof, NonMembershipProof, RangeProof, SparseMerkleTree, UpdateProof,
 };
 use core::convert::{TryFrom, TryInto};
 use padded_key::PaddedKey;
@@ -14,6 +25,15 @@ type Smt<const N: usize> =
     SparseMerkleTree<Blake2bHasher, PaddedKey<N>, H256, DefaultStore<PaddedKey<N>, H256, N>, N>;
 type ShaSmt<const N: usize> =
     SparseMerkleTree<Sha256Hasher, PaddedKey<N>, H256, DefaultStore<PaddedKey<N>, H256, N>, N>;
+type DomainSeparatedSmt<const N: usize> = SparseMerkleTree<
+    DomainSeparated<Sha256Hasher>,
+    PaddedKey<N>,
+    H256,
+    DefaultStore<PaddedKey<N>, H256, N>,
+    N,
+>;
+type Blake2sSmt<const N: usize> =
+    SparseMerkleTree<Blake2sHasher, PaddedKey<N>, H256, DefaultStore<PaddedKey<N>, H256, N>, N>;
 
 #[test]
 fn test_default_root() {
@@ -112,6 +132,673 @@ fn test_validate() {
     assert!(tree.validate());
 }
 
+#[test]
+fn test_from_unsorted_leaves() {
+    let leaves: Vec<(PaddedKey<1>, H256)> = vec![
+        ([1u8].into(), [1u8; 32].into()),
+        ([0u8].into(), [2u8; 32].into()),
+    ];
+    let result = Smt::<1>::from_sorted_leaves(leaves, DefaultStore::default());
+    assert_eq!(result.err(), Some(Error::UnsortedKeys));
+    let leaves: Vec<(PaddedKey<1>, H256)> = vec![
+        ([1u8].into(), [1u8; 32].into()),
+        ([1u8].into(), [2u8; 32].into()),
+    ];
+    let result = Smt::<1>::from_sorted_leaves(leaves, DefaultStore::default());
+    assert_eq!(result.err(), Some(Error::UnsortedKeys));
+}
+
+#[test]
+fn test_update_all_deep_tree() {
+    const N: usize = 300;
+    // each key forks from the next one a bit lower, the tree is 8 * N branches deep
+    let pairs: Vec<(PaddedKey<N>, H256)> = (0..8 * N)
+        .map(|i| {
+            let mut key = InternalKey::<N>::zero();
+            key.set_bit(8 * N - 1 - i);
+            let key: [u8; N] = key.into();
+            (key.into(), [1u8; 32].into())
+        })
+        .collect();
+    // every other key is updated again, over the deep branches
+    let updates: Vec<(PaddedKey<N>, H256)> = pairs
+        .iter()
+        .step_by(2)
+        .map(|(k, _v)| (*k, [2u8; 32].into()))
+        .collect();
+    let mut updated = pairs.clone();
+    updated
+        .iter_mut()
+        .step_by(2)
+        .for_each(|(_k, v)| *v = [2u8; 32].into());
+    updated.sort_by_key(|(k, _v)| **k);
+    // the batch is walked without recursion, on a thread with a small stack
+    std::thread::Builder::new()
+        .stack_size(2 << 20)
+        .spawn(move || {
+            let mut smt = ShaSmt::<N>::default();
+            smt.update_all(pairs.clone()).expect("update all");
+            let mut sorted = pairs;
+            sorted.sort_by_key(|(k, _v)| **k);
+            let expected = ShaSmt::<N>::from_sorted_leaves(sorted, DefaultStore::default())
+                .expect("from sorted leaves");
+            assert_eq!(smt.root(), expected.root());
+
+            smt.update_all(updates).expect("update all");
+            let expected = ShaSmt::<N>::from_sorted_leaves(updated, DefaultStore::default())
+                .expect("from sorted leaves");
+            assert_eq!(smt.root(), expected.root());
+        })
+        .expect("spawn")
+        .join()
+        .expect("update all");
+}
+
+#[test]
+fn test_prefix_iter() {
+    let keys = [
+        "channelEnds/ports/a/channels/channel-0",
+        "channelEnds/ports/x/channels/channel-0",
+        "channelEnds/ports/x/channels/channel-1",
+        "channelEnds/ports/xy/channels/channel-0",
+        "clients/07-tendermint-0/clientState",
+        "commitments/ports/x/channels/channel-0/sequences/1",
+    ];
+    let pairs: Vec<(PaddedKey<64>, H256)> = keys
+        .iter()
+        .map(|k| {
+            let key = PaddedKey::try_from(k.as_bytes().to_vec()).expect("Test failed");
+            (key, [k.len() as u8; 32].into())
+        })
+        .collect();
+    let smt = new_smt::<64>(pairs.clone());
+    let scan = |prefix: &str| -> Vec<PaddedKey<64>> {
+        smt.prefix(prefix.as_bytes())
+            .map(|leaf| leaf.expect("prefix").0)
+            .collect()
+    };
+    assert_eq!(scan("channelEnds/ports/x/"), vec![pairs[1].0, pairs[2].0]);
+    assert_eq!(
+        scan("channelEnds/ports/x"),
+        vec![pairs[1].0, pairs[2].0, pairs[3].0]
+    );
+    assert_eq!(scan("c").len(), keys.len());
+    assert!(scan("connections/").is_empty());
+    assert_eq!(
+        scan(""),
+        smt.iter()
+            .map(|leaf| leaf.expect("iter").0)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_proof_decoding_rejects_malformed() {
+    let proof = MerkleProof::new(vec![vec![3, 7]], vec![(H256::zero(), 3), (H256::zero(), 7)]);
+    let mut bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert_eq!(bytes[0], merkle_proof::PROOF_ENCODING_VERSION);
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_ok());
+    // unknown version
+    bytes[0] = 2;
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_err());
+    // heights of a leaf are not increasing
+    let proof = MerkleProof::new(vec![vec![7, 3]], vec![(H256::zero(), 7)]);
+    let bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_err());
+    // more proof nodes than merge heights
+    let proof = MerkleProof::new(vec![vec![3]], vec![(H256::zero(), 3), (H256::zero(), 3)]);
+    let bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_err());
+    // a proof node merged at a height missing from the leaves path
+    let proof = MerkleProof::new(vec![vec![3, 7]], vec![(H256::zero(), 3), (H256::zero(), 5)]);
+    let bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_err());
+    // a height merging a single leaf, with no proof node nor other leaf
+    let proof = MerkleProof::new(vec![vec![2], vec![2, 5]], vec![(H256::zero(), 5)]);
+    assert!(proof.check::<1>().is_ok());
+    let proof = MerkleProof::new(vec![vec![2], vec![1, 2, 5]], vec![(H256::zero(), 5)]);
+    assert_eq!(proof.check::<1>(), Err(Error::CorruptedProof));
+    let bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_err());
+
+    // heights above the tree are rejected before computing the root
+    let proof = MerkleProof::new(vec![vec![8]], vec![(H256::zero(), 8)]);
+    assert_eq!(proof.check::<1>(), Err(Error::CorruptedProof));
+    let bytes = borsh::to_vec(&proof).expect("encode proof");
+    assert!(borsh::from_slice::<MerkleProof>(&bytes).is_ok());
+    assert_eq!(
+        MerkleProof::decode::<1>(&bytes).unwrap_err(),
+        Error::CorruptedProof
+    );
+    assert!(MerkleProof::decode::<2>(&bytes).is_ok());
+    assert_eq!(
+        MerkleProof::decode::<2>(&bytes[1..]).unwrap_err(),
+        Error::CorruptedProof
+    );
+    let result = proof.compute_root::<Blake2bHasher, PaddedKey<1>, H256, 1>(vec![(
+        [42u8; 1].into(),
+        [42u8; 32].into(),
+    )]);
+    assert_eq!(result.unwrap_err(), Error::CorruptedProof);
+
+    // L, P with a truncated height
+    let mut program = vec![0x4C, 0x50];
+    program.extend_from_slice(&[0u8; 32]);
+    program.extend_from_slice(&[0u8; 4]);
+    let compiled_proof = CompiledMerkleProof(program);
+    assert_eq!(compiled_proof.check::<1>(), Err(Error::CorruptedProof));
+    let bytes = borsh::to_vec(&compiled_proof).expect("encode compiled proof");
+    assert!(borsh::from_slice::<CompiledMerkleProof>(&bytes).is_err());
+    // L, H at height 8 of a 1 byte key tree
+    let mut program = vec![0x4C, 0x4C, 0x48];
+    program.extend_from_slice(&8u64.to_be_bytes());
+    let compiled_proof = CompiledMerkleProof(program);
+    assert_eq!(compiled_proof.check::<1>(), Err(Error::CorruptedProof));
+    assert!(compiled_proof.check::<2>().is_ok());
+    let bytes = borsh::to_vec(&compiled_proof).expect("encode compiled proof");
+    assert!(borsh::from_slice::<CompiledMerkleProof>(&bytes).is_ok());
+    assert_eq!(
+        CompiledMerkleProof::decode::<1>(&bytes).unwrap_err(),
+        Error::CorruptedProof
+    );
+    assert!(CompiledMerkleProof::decode::<2>(&bytes).is_ok());
+    let result = compiled_proof.compute_root::<Blake2bHasher, PaddedKey<1>, H256, 1>(vec![
+        ([1u8; 1].into(), [42u8; 32].into()),
+        ([2u8; 1].into(), [42u8; 32].into()),
+    ]);
+    assert_eq!(result.unwrap_err(), Error::CorruptedProof);
+    // unknown code
+    assert_eq!(
+        CompiledMerkleProof(vec![0x4C, 0x00]).check::<1>(),
+        Err(Error::InvalidCode(0x00))
+    );
+    let bytes = borsh::to_vec(&CompiledMerkleProof(vec![0x4C, 0x00])).expect("encode");
+    assert!(borsh::from_slice::<CompiledMerkleProof>(&bytes).is_err());
+}
+
+#[test]
+fn test_compiled_proof_extra_leaves() {
+    let pairs: Vec<(PaddedKey<1>, H256)> = [0x00u8, 0x04, 0x06]
+        .iter()
+        .map(|i| ([*i].into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_smt::<1>(pairs.clone());
+    let data = vec![pairs[0], pairs[2]];
+    let compiled_proof = smt
+        .merkle_proof(vec![pairs[0].0, pairs[2].0])
+        .expect("gen proof")
+        .compile(data.clone())
+        .expect("compile proof");
+    assert!(
+        verify_compiled::<Blake2bHasher, _, _, 1, 4>(&compiled_proof.0, smt.root(), &data)
+            .expect("verify compiled proof")
+    );
+
+    // a leaf the program doesn't read isn't proven
+    let mut extra = data.clone();
+    extra.push(([0xF0].into(), [42u8; 32].into()));
+    let expected = Err(Error::IncorrectNumberOfLeaves {
+        expected: 2,
+        actual: 3,
+    });
+    assert_eq!(
+        verify_compiled::<Blake2bHasher, _, _, 1, 4>(&compiled_proof.0, smt.root(), &extra),
+        expected
+    );
+    assert_eq!(
+        compiled_proof.verify::<Blake2bHasher, PaddedKey<1>, H256, 1>(smt.root(), extra),
+        expected
+    );
+}
+
+#[test]
+fn test_compiled_proof_disassemble() {
+    let pairs: Vec<(PaddedKey<4>, H256)> = (0u8..20)
+        .map(|i| ([i, i, 0, 1].into(), [i; 32].into()))
+        .collect();
+    let smt = new_smt::<4>(pairs.clone());
+    let data = vec![pairs[3], pairs[4], pairs[11]];
+    let proof = smt
+        .merkle_proof(data.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    let compiled_proof = proof.compile(data.clone()).expect("compile proof");
+
+    let ops = compiled_proof.disassemble().expect("disassemble");
+    assert_eq!(ops.iter().filter(|op| **op == Op::Leaf).count(), 3);
+    assert!(ops.iter().any(|op| matches!(op, Op::Merge(_))));
+    assert!(ops.iter().any(|op| matches!(op, Op::Proof(..))));
+    assert_eq!(ops[0].to_string(), "L");
+    assert_eq!(CompiledMerkleProof::assemble(&ops).0, compiled_proof.0);
+    assert!(compiled_proof.validate::<4>(3).is_ok());
+    assert_eq!(
+        compiled_proof.validate::<4>(2),
+        Err(Error::IncorrectNumberOfLeaves {
+            expected: 3,
+            actual: 2
+        })
+    );
+
+    // a height above the tree is shown but doesn't validate
+    let program = CompiledMerkleProof::assemble(&[Op::Leaf, Op::Proof(40, H256::zero())]);
+    assert_eq!(
+        program.disassemble(),
+        Ok(vec![Op::Leaf, Op::Proof(40, H256::zero())])
+    );
+    assert_eq!(program.validate::<4>(1), Err(Error::CorruptedProof));
+    assert!(program.validate::<8>(1).is_ok());
+    // merging a single node
+    let program = CompiledMerkleProof::assemble(&[Op::Leaf, Op::Merge(3)]);
+    assert_eq!(program.validate::<4>(1), Err(Error::CorruptedStack));
+    // trailing bytes of a truncated op
+    let mut bytes = compiled_proof.0.clone();
+    bytes.extend_from_slice(&[0x48, 0, 0]);
+    assert_eq!(
+        CompiledMerkleProof(bytes).disassemble(),
+        Err(Error::CorruptedProof)
+    );
+    assert_eq!(
+        CompiledMerkleProof(vec![0x4C, 0x00]).disassemble(),
+        Err(Error::InvalidCode(0x00))
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_proof_serde_encoding() {
+    let pairs: Vec<(PaddedKey<4>, H256)> = (0u8..20)
+        .map(|i| ([i, i, 0, 1].into(), [i; 32].into()))
+        .collect();
+    let smt = new_smt::<4>(pairs.clone());
+    let keys = vec![pairs[3].0, pairs[11].0];
+    let data = vec![pairs[3], pairs[11]];
+    let proof = smt.merkle_proof(keys).expect("gen proof");
+    let compiled_proof = proof.clone().compile(data.clone()).expect("compile proof");
+
+    let json = serde_json::to_string(&proof).expect("encode proof");
+    let decoded: MerkleProof = serde_json::from_str(&json).expect("decode proof");
+    assert_eq!(decoded.leaves_path(), proof.leaves_path());
+    assert_eq!(decoded.proof(), proof.proof());
+    assert!(decoded
+        .verify::<Blake2bHasher, PaddedKey<4>, H256, 4>(smt.root(), data.clone())
+        .expect("verify proof"));
+    let json = serde_json::to_string(&compiled_proof).expect("encode compiled proof");
+    let decoded: CompiledMerkleProof = serde_json::from_str(&json).expect("decode compiled proof");
+    assert_eq!(decoded.0, compiled_proof.0);
+
+    // unknown version and malformed proofs are rejected
+    assert!(
+        serde_json::from_str::<MerkleProof>(r#"{"V2":{"leaves_path":[],"proof":[]}}"#).is_err()
+    );
+    assert!(
+        serde_json::from_str::<MerkleProof>(r#"{"V1":{"leaves_path":[[5,5]],"proof":[]}}"#)
+            .is_err()
+    );
+    assert!(serde_json::from_str::<CompiledMerkleProof>(r#"{"V1":[76,76]}"#).is_err());
+    assert!(serde_json::from_str::<CompiledMerkleProof>(r#"{"V1":[76]}"#).is_ok());
+}
+
+#[test]
+fn test_non_membership_merkle_proof_edges() {
+    let key: PaddedKey<8> = [7u8; 8].into();
+    let smt = Smt::<8>::default();
+    let proof = smt.non_membership_merkle_proof(&key).expect("gen proof");
+    assert!(proof
+        .verify::<Blake2bHasher, 8>(smt.root(), &key)
+        .expect("verify proof"));
+
+    // a single leaf tree
+    let smt = new_smt::<8>(vec![([9u8; 8].into(), [1u8; 32].into())]);
+    assert!(!proof
+        .verify::<Blake2bHasher, 8>(smt.root(), &key)
+        .expect("verify proof"));
+    let proof = smt.non_membership_merkle_proof(&key).expect("gen proof");
+    assert!(proof.left().is_none());
+    assert!(proof
+        .verify::<Blake2bHasher, 8>(smt.root(), &key)
+        .expect("verify proof"));
+
+    // keys before and after all the leaves
+    let pairs: Vec<(PaddedKey<8>, H256)> = (1u8..20)
+        .map(|i| ([i * 10; 8].into(), [i; 32].into()))
+        .collect();
+    let smt = new_smt::<8>(pairs);
+    for key in [[0u8; 8], [255u8; 8], [15u8; 8]].iter() {
+        let key: PaddedKey<8> = (*key).into();
+        let proof = smt.non_membership_merkle_proof(&key).expect("gen proof");
+        assert!(proof
+            .verify::<Blake2bHasher, 8>(smt.root(), &key)
+            .expect("verify proof"));
+    }
+}
+
+#[test]
+fn test_tampered_leaves_path() {
+    let pairs: Vec<(PaddedKey<1>, H256)> = [0u8, 4, 6]
+        .iter()
+        .map(|i| ([*i].into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_smt::<1>(pairs.clone());
+    let key: PaddedKey<1> = [4u8].into();
+
+    // the proof of the neighbors 0x00 and 0x06 of 0x04, with the merge of
+    // 0x04 moved below the proof node
+    let proof = smt
+        .merkle_proof(vec![pairs[0].0, pairs[2].0])
+        .expect("gen proof");
+    let (mut leaves_path, nodes) = proof.take();
+    assert_eq!(leaves_path, vec![vec![2], vec![1, 2]]);
+    leaves_path[1][0] = 0;
+    let tampered = MerkleProof::new(leaves_path, nodes.clone());
+    let forged = NonMembershipProof::new(Some(pairs[0]), Some(pairs[2]), tampered.clone());
+    assert!(!matches!(
+        forged.verify::<Blake2bHasher, 1>(smt.root(), &key),
+        Ok(true)
+    ));
+    assert_eq!(
+        tampered.compute_root::<Blake2bHasher, PaddedKey<1>, H256, 1>(vec![pairs[0], pairs[2]]),
+        Err(Error::CorruptedProof)
+    );
+
+    // a leaf path which doesn't end where the leaves merge
+    let tampered = MerkleProof::new(
+        vec![vec![1, 2], vec![2, 3]],
+        vec![nodes[0], (H256::zero(), 3)],
+    );
+    assert!(tampered.check::<1>().is_ok());
+    assert_eq!(
+        tampered
+            .clone()
+            .compute_root::<Blake2bHasher, PaddedKey<1>, H256, 1>(vec![pairs[0], pairs[2]]),
+        Err(Error::CorruptedProof)
+    );
+    assert_eq!(
+        tampered
+            .compile::<PaddedKey<1>, 1>(vec![pairs[0], pairs[2]])
+            .unwrap_err(),
+        Error::CorruptedProof
+    );
+
+    // 0x05 lies between 0x04 and 0x06
+    let key: PaddedKey<1> = [5u8].into();
+    let proof = smt.non_membership_merkle_proof(&key).expect("gen proof");
+    assert!(proof
+        .verify::<Blake2bHasher, 1>(smt.root(), &key)
+        .expect("verify proof"));
+}
+
+fn temp_log_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("smt-{}-{}.log", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+type FileSmt =
+    SparseMerkleTree<Blake2bHasher, PaddedKey<8>, H256, FileStore<PaddedKey<8>, H256, 8>, 8>;
+
+#[test]
+fn test_file_store_reopen() {
+    let path = temp_log_path("reopen");
+    let pairs: Vec<(PaddedKey<8>, H256)> = (0u8..50)
+        .map(|i| ([i, i * 3, 1, 2, 3, 4, 5, i].into(), [i + 1; 32].into()))
+        .collect();
+    let mut smt = FileSmt::new(H256::zero(), FileStore::open(&path).expect("open"));
+    for (key, value) in pairs.iter().take(30) {
+        smt.update(*key, *value).expect("update");
+    }
+    let root = *smt.root();
+    smt.store_mut().commit(&root).expect("commit");
+    // uncommitted changes are lost
+    for (key, value) in pairs.iter().skip(30) {
+        smt.update(*key, *value).expect("update");
+    }
+    assert!(smt.store().has_pending());
+    drop(smt);
+
+    let store = FileStore::open(&path).expect("reopen");
+    assert_eq!(store.root(), &root);
+    let mut smt = FileSmt::new(*store.root(), store);
+    assert!(smt.validate());
+    assert_eq!(smt.get(&pairs[0].0).expect("get"), pairs[0].1);
+    assert_eq!(smt.get(&pairs[40].0).expect("get"), H256::zero());
+
+    // deletes and updates are replayed, also after compacting the log
+    smt.update(pairs[0].0, H256::zero()).expect("update");
+    smt.update_all(pairs[30..].to_vec()).expect("update all");
+    let root = *smt.root();
+    smt.store_mut().commit(&root).expect("commit");
+    let log_len = std::fs::metadata(&path).expect("metadata").len();
+    smt.store_mut().compact().expect("compact");
+    assert!(std::fs::metadata(&path).expect("metadata").len() < log_len);
+    let branches = smt.store().branches_map().len();
+    drop(smt);
+
+    let store = FileStore::<PaddedKey<8>, H256, 8>::open(&path).expect("reopen");
+    assert_eq!(store.branches_map().len(), branches);
+    let smt = FileSmt::new(*store.root(), store);
+    assert_eq!(smt.root(), &root);
+    assert!(smt.validate());
+    assert_eq!(smt.get(&pairs[0].0).expect("get"), H256::zero());
+    assert_eq!(smt.get(&pairs[40].0).expect("get"), pairs[40].1);
+    std::fs::remove_file(&path).expect("remove log");
+}
+
+#[test]
+fn test_file_store_torn_write() {
+    use std::io::Write;
+
+    let path = temp_log_path("torn");
+    let mut smt = FileSmt::new(H256::zero(), FileStore::open(&path).expect("open"));
+    smt.update([1u8; 8].into(), [1u8; 32].into())
+        .expect("update");
+    let root = *smt.root();
+    smt.store_mut().commit(&root).expect("commit");
+    smt.update([2u8; 8].into(), [2u8; 32].into())
+        .expect("update");
+    let root2 = *smt.root();
+    smt.store_mut().commit(&root2).expect("commit");
+    drop(smt);
+
+    // cut the last batch as a crash in the middle of its write
+    let len = std::fs::metadata(&path).expect("metadata").len();
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .expect("open log");
+    file.set_len(len - 10).expect("truncate");
+    drop(file);
+    let store = FileStore::open(&path).expect("reopen");
+    assert_eq!(store.root(), &root);
+    let mut smt = FileSmt::new(*store.root(), store);
+    assert!(smt.validate());
+    assert_eq!(smt.get(&[2u8; 8].into()).expect("get"), H256::zero());
+
+    // the torn batch is dropped, so the next commits are replayed
+    smt.update([3u8; 8].into(), [3u8; 32].into())
+        .expect("update");
+    let root3 = *smt.root();
+    smt.store_mut().commit(&root3).expect("commit");
+    drop(smt);
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .expect("open log");
+    file.write_all(&[0xFF; 20]).expect("write garbage");
+    drop(file);
+    let store = FileStore::<PaddedKey<8>, H256, 8>::open(&path).expect("reopen");
+    assert_eq!(store.root(), &root3);
+    assert!(FileSmt::new(*store.root(), store).validate());
+    std::fs::remove_file(&path).expect("remove log");
+}
+
+#[test]
+fn test_file_store_corrupted_log() {
+    let path = temp_log_path("corrupted");
+    let mut smt = FileSmt::new(H256::zero(), FileStore::open(&path).expect("open"));
+    smt.update([1u8; 8].into(), [1u8; 32].into())
+        .expect("update");
+    let root = *smt.root();
+    smt.store_mut().commit(&root).expect("commit");
+    let first_len = std::fs::metadata(&path).expect("metadata").len();
+    smt.update([2u8; 8].into(), [2u8; 32].into())
+        .expect("update");
+    let root2 = *smt.root();
+    smt.store_mut().commit(&root2).expect("commit");
+    drop(smt);
+    let bytes = std::fs::read(&path).expect("read log");
+
+    // a flipped byte in the last batch is a torn write
+    let mut torn = bytes.clone();
+    *torn.last_mut().unwrap() ^= 1;
+    std::fs::write(&path, &torn).expect("write log");
+    let store = FileStore::<PaddedKey<8>, H256, 8>::open(&path).expect("reopen");
+    assert_eq!(store.root(), &root);
+    drop(store);
+    assert_eq!(std::fs::metadata(&path).expect("metadata").len(), first_len);
+
+    // a flipped byte in a batch followed by another one is a corruption,
+    // the log is left as it is
+    let mut corrupted = bytes.clone();
+    corrupted[first_len as usize - 1] ^= 1;
+    std::fs::write(&path, &corrupted).expect("write log");
+    let result = FileStore::<PaddedKey<8>, H256, 8>::open(&path);
+    assert_eq!(
+        result.unwrap_err(),
+        Error::Store("Corrupted batch at offset 0".to_string())
+    );
+    assert_eq!(std::fs::read(&path).expect("read log"), corrupted);
+
+    // a flipped bit in the length of a batch is a corruption, not a batch
+    // running past the end of the log
+    let mut corrupted = bytes.clone();
+    corrupted[0] ^= 1;
+    std::fs::write(&path, &corrupted).expect("write log");
+    let result = FileStore::<PaddedKey<8>, H256, 8>::open(&path);
+    assert_eq!(
+        result.unwrap_err(),
+        Error::Store("Corrupted batch header at offset 0".to_string())
+    );
+    assert_eq!(std::fs::read(&path).expect("read log"), corrupted);
+    let mut corrupted = bytes.clone();
+    corrupted[first_len as usize + 7] ^= 0x80;
+    std::fs::write(&path, &corrupted).expect("write log");
+    let result = FileStore::<PaddedKey<8>, H256, 8>::open(&path);
+    assert_eq!(
+        result.unwrap_err(),
+        Error::Store(format!("Corrupted batch header at offset {}", first_len))
+    );
+    assert_eq!(std::fs::read(&path).expect("read log"), corrupted);
+    std::fs::remove_file(&path).expect("remove log");
+}
+
+/// Store failing to read and write the nodes once its countdown of accesses
+/// is over, if any
+#[derive(Debug, Default)]
+struct FailingStore {
+    store: DefaultStore<PaddedKey<8>, H256, 8>,
+    countdown: std::rc::Rc<std::cell::Cell<Option<usize>>>,
+}
+
+impl FailingStore {
+    fn check(&self) -> Result<(), Error> {
+        match self.countdown.get() {
+            Some(0) => Err(Error::Store("failing store".to_string())),
+            Some(count) => {
+                self.countdown.set(Some(count - 1));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl traits::Store<PaddedKey<8>, H256, 8> for FailingStore {
+    fn get_branch(&self, node: &H256) -> Result<Option<tree::BranchNode<PaddedKey<8>, 8>>, Error> {
+        self.check()?;
+        self.store.get_branch(node)
+    }
+    fn get_leaf(
+        &self,
+        leaf_key: &H256,
+    ) -> Result<Option<tree::LeafNode<PaddedKey<8>, H256, 8>>, Error> {
+        self.check()?;
+        self.store.get_leaf(leaf_key)
+    }
+    fn insert_branch(
+        &mut self,
+        node: H256,
+        branch: tree::BranchNode<PaddedKey<8>, 8>,
+    ) -> Result<(), Error> {
+        self.check()?;
+        self.store.insert_branch(node, branch)
+    }
+    fn insert_leaf(
+        &mut self,
+        leaf_key: H256,
+        leaf: tree::LeafNode<PaddedKey<8>, H256, 8>,
+    ) -> Result<(), Error> {
+        self.check()?;
+        self.store.insert_leaf(leaf_key, leaf)
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<(), Error> {
+        self.check()?;
+        self.store.remove_branch(node)
+    }
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), Error> {
+        self.check()?;
+        self.store.remove_leaf(leaf_key)
+    }
+    fn sorted_leaves<'a>(&'a self) -> impl Iterator<Item = (PaddedKey<8>, &'a H256)>
+    where
+        H256: 'a,
+    {
+        self.store.sorted_leaves()
+    }
+    fn size(&self) -> usize {
+        self.store.size()
+    }
+}
+
+type FailingSmt = SparseMerkleTree<Blake2bHasher, PaddedKey<8>, H256, FailingStore, 8>;
+
+#[test]
+fn test_transaction_failed_update() {
+    let pairs: Vec<(PaddedKey<8>, H256)> =
+        (1u8..10).map(|i| ([i; 8].into(), [i; 32].into())).collect();
+    let store = FailingStore::default();
+    let countdown = store.countdown.clone();
+    let mut smt = FailingSmt::new(H256::zero(), store);
+    smt.update_all(pairs.clone()).expect("update");
+    let root = *smt.root();
+
+    let mut tx = smt.begin();
+    tx.update(pairs[0].0, [42u8; 32].into()).expect("update");
+    let staged_root = *tx.root();
+    // the update fails on the backing tree after staging some changes
+    countdown.set(Some(2));
+    assert_eq!(
+        tx.update(pairs[5].0, H256::zero()),
+        Err(Error::Store("failing store".to_string()))
+    );
+    countdown.set(None);
+    assert_eq!(tx.root(), &staged_root);
+    assert_eq!(tx.get(&pairs[0].0).expect("get"), [42u8; 32].into());
+    assert_eq!(tx.get(&pairs[5].0).expect("get"), pairs[5].1);
+    tx.update(pairs[5].0, H256::zero()).expect("update");
+    let staged_root = *tx.root();
+    tx.commit().expect("commit");
+
+    let mut expected = Smt::<8>::default();
+    expected.update_all(pairs.clone()).expect("update");
+    expected
+        .update(pairs[0].0, [42u8; 32].into())
+        .expect("update");
+    expected.update(pairs[5].0, H256::zero()).expect("update");
+    assert_ne!(&root, expected.root());
+    assert_eq!(&staged_root, expected.root());
+    assert_eq!(smt.root(), expected.root());
+    assert!(smt.validate());
+}
+
 #[test]
 fn test_merkle_root() {
     fn new_blake2b() -> blake2b_rs::Blake2b {
@@ -316,7 +1003,6 @@ fn new_sha_smt<const N: usize>(pairs: Vec<(PaddedKey<N>, H256)>) -> ShaSmt<N> {
 fn test_ics23_non_membership_proof() {
     use rand::Rng;
     let pairs: Vec<(PaddedKey<115>, H256)> = (0u8..20)
-        .into_iter()
         .map(|i| {
             (
                 PaddedKey::<115>::try_from(vec![i; 29]).expect("Test failed"),
@@ -325,7 +1011,7 @@ fn test_ics23_non_membership_proof() {
         })
         .collect();
     let smt = new_sha_smt::<115>(pairs);
-    let spec = proof_ics23::get_spec(ics23::HashOp::Sha256);
+    let spec = proof_ics23::get_spec::<115>(ics23::HashOp::Sha256);
     let root = smt.root().as_slice().to_vec();
     let non_existent_key =
         PaddedKey::<115>::try_from("Non existent key".as_bytes().to_vec()).expect("Test failed");
@@ -344,11 +1030,613 @@ fn test_ics23_non_membership_proof() {
     ));
 }
 
+#[test]
+fn test_ics23_deep_keys() {
+    // the keys fork at height 256, above the height of a tree with 32 bytes keys
+    let mut key = [1u8; 40];
+    let pairs: Vec<(PaddedKey<40>, H256)> = (0u8..4)
+        .map(|i| {
+            key[7] = i;
+            key[39] = i;
+            (key.into(), [i + 1; 32].into())
+        })
+        .collect();
+    let smt = new_sha_smt::<40>(pairs.clone());
+    let spec = proof_ics23::get_spec::<40>(ics23::HashOp::Sha256);
+    let root = smt.root().as_slice().to_vec();
+    for (k, v) in pairs {
+        let proof = smt.membership_proof(&k).expect("gen proof");
+        assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &k.to_vec(),
+            v.as_slice()
+        ));
+        let compiled_proof = smt
+            .merkle_proof(vec![k])
+            .expect("gen proof")
+            .compile(vec![(k, v)])
+            .expect("compile proof");
+        assert!(compiled_proof
+            .verify::<Sha256Hasher, PaddedKey<40>, H256, 40>(smt.root(), vec![(k, v)])
+            .expect("verify compiled proof"));
+    }
+}
+
+#[test]
+fn test_ics23_proof_too_deep() {
+    // 512 leaves of 2 bytes keys need proofs deeper than 8
+    let pairs: Vec<(PaddedKey<2>, H256)> = (0u16..512)
+        .map(|i| (i.to_be_bytes().into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_sha_smt::<2>(pairs);
+    let root = smt.root().as_slice().to_vec();
+    let key: PaddedKey<2> = 42u16.to_be_bytes().into();
+    let proof = smt.membership_proof(&key).expect("gen proof");
+    assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+        &proof,
+        &proof_ics23::get_spec::<2>(ics23::HashOp::Sha256),
+        &root,
+        &key.to_vec(),
+        [42u8; 32].as_slice()
+    ));
+    // the same proof is too deep for a tree of 1 byte keys
+    let spec = proof_ics23::get_spec::<1>(ics23::HashOp::Sha256);
+    assert_eq!(spec.max_depth, 8);
+    assert!(!proof_ics23::check_depth(&proof, &spec));
+    assert!(
+        !proof_ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &key.to_vec(),
+            [42u8; 32].as_slice()
+        )
+    );
+    let non_member: PaddedKey<2> = [255u8, 255].into();
+    let non_membership_proof = smt.non_membership_proof(&non_member).expect("gen proof");
+    assert!(!proof_ics23::verify_non_membership::<
+        ics23::HostFunctionsManager,
+    >(
+        &non_membership_proof, &spec, &root, &non_member.to_vec()
+    ));
+
+    // the proof of a tree with a single leaf has no inner op
+    let smt = new_sha_smt::<1>(vec![([7u8].into(), [42u8; 32].into())]);
+    let proof = smt.membership_proof(&[7u8].into()).expect("gen proof");
+    assert!(
+        proof_ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            smt.root().as_slice(),
+            &[7u8],
+            [42u8; 32].as_slice()
+        )
+    );
+}
+
+#[test]
+fn test_blake2s_hasher() {
+    let mut hasher = Blake2sHasher::default();
+    hasher.write_bytes(b"abc");
+    assert_eq!(
+        hex::encode(hasher.finish().as_slice()),
+        "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+    );
+}
+
+#[test]
+fn test_domain_separated_hashing() {
+    let pairs: Vec<(PaddedKey<4>, H256)> = (0u8..20)
+        .map(|i| ([i, 0, i, 1].into(), [i + 1; 32].into()))
+        .collect();
+    let smt = new_sha_smt::<4>(pairs.clone());
+    let mut ds_smt = DomainSeparatedSmt::<4>::default();
+    for (key, value) in pairs.iter() {
+        ds_smt.update(*key, *value).expect("update");
+    }
+    assert_ne!(smt.root(), ds_smt.root());
+    assert!(ds_smt.validate());
+
+    // the height is mixed into the branches
+    let (lhs, rhs): (H256, H256) = ([1u8; 32].into(), [2u8; 32].into());
+    assert_ne!(
+        merge::merge::<DomainSeparated<Sha256Hasher>>(3, &lhs, &rhs),
+        merge::merge::<DomainSeparated<Sha256Hasher>>(4, &lhs, &rhs)
+    );
+    assert_eq!(
+        merge::merge::<Sha256Hasher>(3, &lhs, &rhs),
+        merge::merge::<Sha256Hasher>(4, &lhs, &rhs)
+    );
+
+    // proofs only verify with the spec of their hashing scheme
+    let (key, value) = pairs[7];
+    let proof = ds_smt.membership_proof(&key).expect("gen proof");
+    let root = ds_smt.root().as_slice().to_vec();
+    let spec = proof_ics23::get_domain_separated_spec::<4>(ics23::HashOp::Sha256);
+    assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+        &proof,
+        &spec,
+        &root,
+        &key.to_vec(),
+        value.as_slice()
+    ));
+    let legacy_spec = proof_ics23::get_spec::<4>(ics23::HashOp::Sha256);
+    assert!(!ics23::verify_membership::<ics23::HostFunctionsManager>(
+        &proof,
+        &legacy_spec,
+        &root,
+        &key.to_vec(),
+        value.as_slice()
+    ));
+}
+
+#[test]
+fn test_ics23_blake2s_proofs() {
+    let mut smt = Blake2sSmt::<32>::default();
+    let keys: Vec<PaddedKey<32>> = (0u8..20).map(|i| [i * 3; 32].into()).collect();
+    for (i, key) in keys.iter().enumerate() {
+        smt.update(*key, [i as u8 + 1; 32].into()).expect("update");
+    }
+    let spec = proof_ics23::get_spec::<32>(Blake2sHasher::hash_op());
+    let root = smt.root().as_slice().to_vec();
+    for (i, key) in keys.iter().enumerate() {
+        let proof = smt.membership_proof(key).expect("gen proof");
+        assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &key.to_vec(),
+            &[i as u8 + 1; 32]
+        ));
+    }
+    for i in 0u8..20 {
+        let key: PaddedKey<32> = [i * 3 + 1; 32].into();
+        let proof = smt.non_membership_proof(&key).expect("gen proof");
+        assert!(ics23::verify_non_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &key.to_vec()
+        ));
+    }
+}
+
+#[cfg(feature = "keccak256")]
+#[test]
+fn test_keccak256_known_vectors() {
+    use crate::keccak256::{Keccak256Hasher, SolidityProof};
+    type KeccakSmt =
+        SparseMerkleTree<Keccak256Hasher, Hash, H256, DefaultStore<Hash, H256, 32>, 32>;
+
+    let hash = |data: &[u8]| {
+        let mut hasher = Keccak256Hasher::default();
+        hasher.write_bytes(data);
+        hex::encode(hasher.finish().as_slice())
+    };
+    assert_eq!(
+        hash(b""),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        hash(b"abc"),
+        "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+    );
+
+    let mut key1 = [0u8; 32];
+    key1[31] = 1;
+    let mut key2 = [0u8; 32];
+    key2[0] = 0x80;
+    let mut key3 = [0u8; 32];
+    key3[31] = 3;
+    let leaves: Vec<(Hash, H256)> = vec![
+        (key1.into(), [0x11; 32].into()),
+        (key2.into(), [0x22; 32].into()),
+        (key3.into(), [0x33; 32].into()),
+    ];
+    // keccak256(abi.encodePacked(bytes32(0), key1, value1))
+    assert_eq!(
+        hex::encode(
+            merge::hash_leaf::<Keccak256Hasher, Hash, H256, 32>(&leaves[0].0, &leaves[0].1)
+                .as_slice()
+        ),
+        "c601c6311ec28e1714fc91461ab54acad03b131793d7461e69192c870d929f70"
+    );
+    // the roots are computed apart from the tree with the formulas of the
+    // contract, the root of a single leaf is its hash
+    let mut smt = KeccakSmt::default();
+    smt.update(leaves[0].0, leaves[0].1).expect("update");
+    assert_eq!(
+        hex::encode(smt.root().as_slice()),
+        "c601c6311ec28e1714fc91461ab54acad03b131793d7461e69192c870d929f70"
+    );
+    let merkle_proof = smt.merkle_proof(vec![leaves[0].0]).expect("gen proof");
+    let proof = SolidityProof::from_merkle_proof(&merkle_proof).expect("solidity proof");
+    assert_eq!(proof, SolidityProof::new(H256::zero(), vec![]));
+    assert!(proof.verify(smt.root(), &leaves[0].0, &leaves[0].1));
+    assert!(!proof.verify(smt.root(), &leaves[1].0, &leaves[0].1));
+    // keccak256(abi.encodePacked(leaf1, leaf2))
+    smt.update(leaves[1].0, leaves[1].1).expect("update");
+    assert_eq!(
+        hex::encode(smt.root().as_slice()),
+        "139412d3f1aabe4fe22a1d5a792e8d08483ca6dbd723cccdd1e94c171c7c75b3"
+    );
+    // keccak256(abi.encodePacked(keccak256(abi.encodePacked(leaf1, leaf3)), leaf2))
+    smt.update(leaves[2].0, leaves[2].1).expect("update");
+    assert_eq!(
+        hex::encode(smt.root().as_slice()),
+        "c4a3964e593331c6dfb963e146f327c518acd8982c82375101f9812bead5298d"
+    );
+
+    for (key, value) in &leaves {
+        let merkle_proof = smt.merkle_proof(vec![*key]).expect("gen proof");
+        assert!(merkle_proof
+            .clone()
+            .verify::<Keccak256Hasher, Hash, H256, 32>(smt.root(), vec![(*key, *value)])
+            .expect("verify proof"));
+        let proof = SolidityProof::from_merkle_proof(&merkle_proof).expect("solidity proof");
+        assert!(proof.verify(smt.root(), key, value));
+        assert!(!proof.verify(smt.root(), key, &[0x44; 32].into()));
+        assert!(!proof.verify(smt.root(), key, &H256::zero()));
+        let mut siblings = proof.siblings().to_vec();
+        siblings.push(H256::zero());
+        assert!(!SolidityProof::new(*proof.bitmap(), siblings).verify(smt.root(), key, value));
+
+        let spec = proof_ics23::get_spec::<32>(Keccak256Hasher::hash_op());
+        let proof = smt.membership_proof(key).expect("gen proof");
+        assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &smt.root().as_slice().to_vec(),
+            &key.to_vec(),
+            value.as_slice()
+        ));
+    }
+    // the leaves 1 and 3 merge at height 1 then with the leaf 2 at height 255
+    let proof =
+        SolidityProof::from_merkle_proof(&smt.merkle_proof(vec![leaves[0].0]).expect("gen proof"))
+            .expect("solidity proof");
+    let mut bitmap = H256::zero();
+    bitmap.set_bit(1);
+    bitmap.set_bit(255);
+    assert_eq!(proof.bitmap(), &bitmap);
+    let merkle_proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    assert_eq!(
+        SolidityProof::from_merkle_proof(&merkle_proof),
+        Err(Error::IncorrectNumberOfLeaves {
+            expected: 1,
+            actual: 3
+        })
+    );
+}
+
+#[cfg(feature = "poseidon")]
+#[test]
+fn test_poseidon_hasher() {
+    use crate::poseidon::{poseidon, Fr, PoseidonHasher};
+    type PoseidonSmt =
+        SparseMerkleTree<PoseidonHasher, Hash, H256, DefaultStore<Hash, H256, 32>, 32>;
+
+    // Poseidon([1, 2]) of circomlib
+    let hash = poseidon(&Fr::from(1), &Fr::from(2));
+    assert_eq!(
+        hex::encode(hash.to_h256().as_slice()),
+        "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+    );
+    assert_eq!(Fr::from_h256(&hash.to_h256()), Some(hash));
+    assert_eq!(Fr::from_h256(&[0xff; 32].into()), None);
+    // the branches are hashed as field elements
+    let mut hasher = PoseidonHasher::default();
+    hasher.write_bytes(Fr::from(1).to_h256().as_slice());
+    hasher.write_bytes(Fr::from(2).to_h256().as_slice());
+    assert_eq!(hasher.finish(), hash.to_h256());
+    // other inputs go through the sponge
+    let mut hasher = PoseidonHasher::default();
+    hasher.write_bytes(&[0xff; 64]);
+    let sponge = hasher.finish();
+    assert!(Fr::from_h256(&sponge).is_some());
+    let mut hasher = PoseidonHasher::default();
+    hasher.write_bytes(&[0xff; 63]);
+    assert_ne!(hasher.finish(), sponge);
+
+    let mut smt = PoseidonSmt::default();
+    let leaves: Vec<(Hash, H256)> = (1u8..=20)
+        .map(|i| ([i.wrapping_mul(37); 32].into(), [i; 32].into()))
+        .collect();
+    for (key, value) in &leaves {
+        smt.update(*key, *value).expect("update");
+    }
+    let proven = vec![leaves[2], leaves[5], leaves[17]];
+    let merkle_proof = smt
+        .merkle_proof(proven.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    let witness =
+        witness::Witness::new::<PoseidonHasher, 32>(merkle_proof, proven).expect("witness");
+    assert_eq!(witness.root(), smt.root());
+    for leaf in witness.leaves() {
+        assert_eq!(leaf.siblings().len(), 256);
+        assert_eq!(leaf.compute_root::<PoseidonHasher>(), *smt.root());
+        // every node of the path is a field element
+        assert!(leaf
+            .siblings()
+            .iter()
+            .all(|sibling| Fr::from_h256(sibling).is_some()));
+    }
+}
+
+#[test]
+fn test_byte_values() {
+    type VecSmt =
+        SparseMerkleTree<Sha256Hasher, Hash, Vec<u8>, DefaultStore<Hash, Vec<u8>, 32>, 32>;
+    type BoxSmt =
+        SparseMerkleTree<Sha256Hasher, Hash, Box<[u8]>, DefaultStore<Hash, Box<[u8]>, 32>, 32>;
+
+    let leaves: Vec<(Hash, Vec<u8>)> = (1u8..=10)
+        .map(|i| ([i.wrapping_mul(53); 32].into(), vec![i; i as usize * 7]))
+        .collect();
+    let mut vec_smt = VecSmt::default();
+    let mut box_smt = BoxSmt::default();
+    for (key, value) in &leaves {
+        vec_smt.update(*key, value.clone()).expect("update");
+        box_smt
+            .update(*key, value.clone().into_boxed_slice())
+            .expect("update");
+    }
+    assert_eq!(vec_smt.root(), box_smt.root());
+    assert_eq!(vec_smt.get(&leaves[3].0).expect("get"), leaves[3].1);
+
+    let proven = vec![leaves[1].clone(), leaves[6].clone()];
+    let proof = vec_smt
+        .merkle_proof(proven.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    assert!(proof
+        .clone()
+        .verify::<Sha256Hasher, Hash, Vec<u8>, 32>(vec_smt.root(), proven.clone())
+        .expect("verify"));
+    let mut forged = proven;
+    forged[0].1.push(0);
+    assert!(!proof
+        .verify::<Sha256Hasher, Hash, Vec<u8>, 32>(vec_smt.root(), forged)
+        .expect("verify"));
+
+    // the empty bytes delete the leaves
+    for (key, _value) in &leaves {
+        vec_smt.update(*key, Vec::new()).expect("update");
+        box_smt.update(*key, Box::default()).expect("update");
+    }
+    assert!(vec_smt.is_empty());
+    assert!(box_smt.is_empty());
+}
+
+#[test]
+fn test_byte_values_with_variable_length_keys() {
+    type VecSmt = SparseMerkleTree<
+        Sha256Hasher,
+        PaddedKey<2>,
+        Vec<u8>,
+        DefaultStore<PaddedKey<2>, Vec<u8>, 2>,
+        2,
+    >;
+
+    // the raw bytes of both leaves would be "abc"
+    let short = PaddedKey::<2>::try_from(b"a".to_vec()).expect("key");
+    let long = PaddedKey::<2>::try_from(b"ab".to_vec()).expect("key");
+    let mut smt = VecSmt::default();
+    smt.update(short, b"bc".to_vec()).expect("update");
+    smt.update(long, b"c".to_vec()).expect("update");
+    assert_eq!(smt.get(&short).expect("get"), b"bc".to_vec());
+    assert_eq!(smt.get(&long).expect("get"), b"c".to_vec());
+    assert!(smt.validate());
+
+    let spec =
+        proof_ics23::get_tree_spec::<Sha256Hasher, PaddedKey<2>, Vec<u8>, 2>().expect("spec");
+    let root = smt.root().as_slice().to_vec();
+    for (key, value) in [(short, b"bc".to_vec()), (long, b"c".to_vec())] {
+        let proof = smt.membership_proof(&key).expect("gen proof");
+        assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &key.to_vec(),
+            &value
+        ));
+    }
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn test_borsh_values() {
+    use crate::borsh_value::BorshValue;
+    use crate::traits::Value;
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    #[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+    struct Account {
+        balance: u64,
+        data: Vec<u8>,
+    }
+    type AccountSmt = SparseMerkleTree<
+        Sha256Hasher,
+        Hash,
+        BorshValue<Account>,
+        DefaultStore<Hash, BorshValue<Account>, 32>,
+        32,
+    >;
+    type VecSmt =
+        SparseMerkleTree<Sha256Hasher, Hash, Vec<u8>, DefaultStore<Hash, Vec<u8>, 32>, 32>;
+
+    let mut account_smt = AccountSmt::default();
+    let mut vec_smt = VecSmt::default();
+    for i in 0u8..10 {
+        let key: Hash = [i; 32].into();
+        // the default account is stored like any other
+        let account = Account {
+            balance: i as u64,
+            data: vec![i; i as usize],
+        };
+        let value = BorshValue::new(account.clone()).expect("encode");
+        assert_eq!(
+            value.encoding(),
+            borsh::to_vec(&account).unwrap().as_slice()
+        );
+        vec_smt
+            .update(key, value.encoding().to_vec())
+            .expect("update");
+        account_smt.update(key, value).expect("update");
+    }
+    // the tree commits to the encodings
+    assert_eq!(account_smt.root(), vec_smt.root());
+    let value = account_smt.get(&[3u8; 32].into()).expect("get");
+    assert_eq!(
+        value.value(),
+        Some(&Account {
+            balance: 3,
+            data: vec![3; 3]
+        })
+    );
+
+    // the value is stored as its encoding
+    let bytes = borsh::to_vec(&value).unwrap();
+    assert_eq!(
+        BorshValue::<Account>::try_from_slice(&bytes).unwrap(),
+        value
+    );
+    let zero = BorshValue::<Account>::zero();
+    let bytes = borsh::to_vec(&zero).unwrap();
+    let decoded = BorshValue::<Account>::try_from_slice(&bytes).unwrap();
+    assert!(decoded.is_zero());
+    assert_eq!(decoded.into_inner(), None);
+    assert!(BorshValue::<Account>::try_from_slice(&borsh::to_vec(&vec![1u8]).unwrap()).is_err());
+
+    // a value without an encoding is the zero value
+    assert!(BorshValue::new(()).expect("encode").is_zero());
+    account_smt.update([3u8; 32].into(), zero).expect("update");
+    assert!(account_smt.get(&[3u8; 32].into()).expect("get").is_zero());
+    assert_eq!(account_smt.store().leaves_map().len(), 9);
+}
+
+#[test]
+fn test_blob_tree() {
+    use crate::blob_store::{blob_digest, BlobTree, DefaultBlobStore};
+    type ShaBlobTree =
+        BlobTree<Sha256Hasher, Hash, DefaultStore<Hash, H256, 32>, DefaultBlobStore, 32>;
+    type DigestSmt = SparseMerkleTree<Sha256Hasher, Hash, H256, DefaultStore<Hash, H256, 32>, 32>;
+
+    let leaves: Vec<(Hash, Vec<u8>)> = (1u8..=10)
+        .map(|i| ([i; 32].into(), vec![i % 4; 1024 * i as usize]))
+        .collect();
+    let mut blob_tree = ShaBlobTree::default();
+    let mut digest_smt = DigestSmt::default();
+    for (key, blob) in &leaves {
+        blob_tree.update(*key, blob.clone()).expect("update");
+        digest_smt
+            .update(*key, blob_digest::<Sha256Hasher>(blob))
+            .expect("update");
+    }
+    // the leaves hold the digests, the blobs are kept once
+    assert_eq!(blob_tree.root(), digest_smt.root());
+    assert_eq!(blob_tree.blobs().blobs_map().len(), 10);
+    assert_eq!(blob_tree.get(&leaves[4].0).expect("get"), leaves[4].1);
+    assert_eq!(
+        blob_tree.get(&[0u8; 32].into()).expect("get"),
+        Vec::<u8>::new()
+    );
+
+    // proofs carry the digests
+    let proven: Vec<(Hash, H256)> = [1, 7]
+        .iter()
+        .map(|&i| (leaves[i].0, blob_digest::<Sha256Hasher>(&leaves[i].1)))
+        .collect();
+    let proof = blob_tree
+        .merkle_proof(proven.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    assert!(proof
+        .verify::<Sha256Hasher, Hash, H256, 32>(blob_tree.root(), proven)
+        .expect("verify"));
+
+    // identical blobs are shared until the last leaf drops them
+    let shared = leaves[2].1.clone();
+    blob_tree
+        .update(leaves[3].0, shared.clone())
+        .expect("update");
+    assert_eq!(blob_tree.blobs().blobs_map().len(), 9);
+    blob_tree.update(leaves[2].0, Vec::new()).expect("update");
+    assert_eq!(blob_tree.get(&leaves[3].0).expect("get"), shared);
+    blob_tree.update(leaves[3].0, Vec::new()).expect("update");
+    assert_eq!(blob_tree.blobs().blobs_map().len(), 8);
+
+    for (key, _blob) in &leaves {
+        blob_tree.update(*key, Vec::new()).expect("update");
+    }
+    assert!(blob_tree.tree().is_empty());
+    assert!(blob_tree.blobs().blobs_map().is_empty());
+
+    // the blobs are not in the tree store
+    let blob_tree = ShaBlobTree::new(digest_smt, DefaultBlobStore::default());
+    let digest = blob_digest::<Sha256Hasher>(&leaves[0].1);
+    assert_eq!(blob_tree.get_digest(&leaves[0].0).expect("get"), digest);
+    assert_eq!(blob_tree.get(&leaves[0].0), Err(Error::MissingBlob(digest)));
+}
+
+/// Blob store failing to insert the blobs while `failing` is set
+#[derive(Debug, Default)]
+struct FailingBlobStore {
+    blobs: crate::blob_store::DefaultBlobStore,
+    failing: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+impl traits::BlobStore for FailingBlobStore {
+    fn get_blob(&self, digest: &H256) -> Result<Option<Vec<u8>>, Error> {
+        self.blobs.get_blob(digest)
+    }
+    fn insert_blob(&mut self, digest: H256, blob: Vec<u8>) -> Result<(), Error> {
+        if self.failing.get() {
+            return Err(Error::Store("failing blob store".to_string()));
+        }
+        self.blobs.insert_blob(digest, blob)
+    }
+    fn remove_blob(&mut self, digest: &H256) -> Result<(), Error> {
+        self.blobs.remove_blob(digest)
+    }
+}
+
+#[test]
+fn test_blob_tree_failed_update() {
+    use crate::blob_store::BlobTree;
+    type FailingBlobTree = BlobTree<Blake2bHasher, PaddedKey<8>, FailingStore, FailingBlobStore, 8>;
+
+    let store = FailingStore::default();
+    let countdown = store.countdown.clone();
+    let blobs = FailingBlobStore::default();
+    let failing = blobs.failing.clone();
+    let mut blob_tree = FailingBlobTree::new(FailingSmt::new(H256::zero(), store), blobs);
+    let key: PaddedKey<8> = [1u8; 8].into();
+
+    // the new blob is dropped if the tree can't commit to it
+    countdown.set(Some(0));
+    assert!(blob_tree.update(key, vec![1; 64]).is_err());
+    countdown.set(None);
+    assert!(blob_tree.root().is_zero());
+    assert!(blob_tree.blobs().blobs.blobs_map().is_empty());
+
+    // the tree keeps the old blob if the new one can't be stored
+    blob_tree.update(key, vec![1; 64]).expect("update");
+    let root = *blob_tree.root();
+    failing.set(true);
+    assert!(blob_tree.update(key, vec![2; 64]).is_err());
+    failing.set(false);
+    assert_eq!(blob_tree.root(), &root);
+    assert_eq!(blob_tree.get(&key).expect("get"), vec![1; 64]);
+    assert_eq!(blob_tree.blobs().blobs.blobs_map().len(), 1);
+}
+
 #[test]
 fn test_ics23_membership_proof() {
     use rand::Rng;
     let pairs: Vec<(PaddedKey<115>, H256)> = (0u8..20)
-        .into_iter()
         .map(|i| {
             (
                 PaddedKey::<115>::try_from(vec![i; 29]).expect("Test failed"),
@@ -357,7 +1645,7 @@ fn test_ics23_membership_proof() {
         })
         .collect();
     let mut smt = new_sha_smt::<115>(pairs);
-    let spec = proof_ics23::get_spec(ics23::HashOp::Sha256);
+    let spec = proof_ics23::get_spec::<115>(ics23::HashOp::Sha256);
     let existent_key =
         PaddedKey::<115>::try_from("Existent key".as_bytes().to_vec()).expect("Test failed");
     smt.update(existent_key, H256::from([42u8; 32]))
@@ -377,6 +1665,155 @@ fn test_ics23_membership_proof() {
     ));
 }
 
+#[test]
+fn test_hashed_key_ics23() {
+    type HashedSmt = SparseMerkleTree<
+        Sha256Hasher,
+        HashedKey<Sha256Hasher>,
+        H256,
+        DefaultStore<HashedKey<Sha256Hasher>, H256, 32>,
+        32,
+    >;
+    type HashSmt = SparseMerkleTree<Sha256Hasher, Hash, H256, DefaultStore<Hash, H256, 32>, 32>;
+
+    let key = |i: usize| {
+        HashedKey::<Sha256Hasher>::new(format!("accounts/{}", i).as_bytes()).expect("key")
+    };
+    let mut smt = HashedSmt::default();
+    let mut hash_smt = HashSmt::default();
+    for i in 0..50 {
+        let value: H256 = [i as u8 + 1; 32].into();
+        smt.update(key(i), value).expect("update");
+        let mut hasher = Sha256Hasher::default();
+        hasher.write_bytes(format!("accounts/{}", i).as_bytes());
+        hash_smt
+            .update(hasher.finish().into(), value)
+            .expect("update");
+    }
+    // the keys are placed and committed at their hashes
+    assert_eq!(smt.root(), hash_smt.root());
+    assert_eq!(key(7).as_slice(), b"accounts/7");
+    assert_eq!(smt.get(&key(7)).expect("get"), [8u8; 32].into());
+    assert_eq!(
+        HashedKey::<Sha256Hasher, 4>::new(b"accounts").unwrap_err(),
+        Error::KeyTooLarge
+    );
+    #[cfg(feature = "borsh")]
+    {
+        use borsh::BorshDeserialize;
+        let bytes = borsh::to_vec(&key(7)).unwrap();
+        assert_eq!(
+            HashedKey::<Sha256Hasher>::try_from_slice(&bytes).unwrap(),
+            key(7)
+        );
+    }
+
+    let spec =
+        proof_ics23::get_prehashed_key_spec::<32>(ics23::HashOp::Sha256, ics23::HashOp::Sha256)
+            .expect("spec");
+    let root = smt.root().as_slice().to_vec();
+    let proof = smt.membership_proof(&key(7)).expect("gen proof");
+    assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(
+        &proof,
+        &spec,
+        &root,
+        b"accounts/7",
+        [8u8; 32].as_slice()
+    ));
+    // the key isn't prehashed by the plain spec
+    let plain_spec = proof_ics23::get_spec::<32>(ics23::HashOp::Sha256);
+    assert!(!ics23::verify_membership::<ics23::HostFunctionsManager>(
+        &proof,
+        &plain_spec,
+        &root,
+        b"accounts/7",
+        [8u8; 32].as_slice()
+    ));
+
+    // the neighbors of the absent keys are ordered by hash
+    for i in 50..60 {
+        let proof = smt.non_membership_proof(&key(i)).expect("gen proof");
+        assert!(ics23::verify_non_membership::<ics23::HostFunctionsManager>(
+            &proof,
+            &spec,
+            &root,
+            &key(i).to_vec()
+        ));
+    }
+
+    let keys: Vec<_> = (40..60).map(key).collect();
+    let proof = smt.compressed_batch_proof(&keys).expect("gen batch proof");
+    let exists: Vec<(Vec<u8>, Vec<u8>)> = (40..50)
+        .map(|i| (key(i).to_vec(), vec![i as u8 + 1; 32]))
+        .collect();
+    let items = exists
+        .iter()
+        .map(|(k, v)| (k.as_slice(), v.as_slice()))
+        .collect();
+    assert!(
+        ics23::verify_batch_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, items)
+    );
+    let non_exists: Vec<Vec<u8>> = (50..60).map(|i| key(i).to_vec()).collect();
+    let non_exists: Vec<&[u8]> = non_exists.iter().map(|k| k.as_slice()).collect();
+    assert!(ics23::verify_batch_non_membership::<
+        ics23::HostFunctionsManager,
+    >(&proof, &spec, &root, &non_exists));
+}
+
+#[test]
+fn test_hashed_key_ics23_without_hash_op() {
+    // blake2b has no ICS23 hash operation to prehash the keys
+    type HashedSmt = SparseMerkleTree<
+        Blake2bHasher,
+        HashedKey<Blake2bHasher>,
+        H256,
+        DefaultStore<HashedKey<Blake2bHasher>, H256, 32>,
+        32,
+    >;
+
+    let key = |i: usize| {
+        HashedKey::<Blake2bHasher>::new(format!("accounts/{}", i).as_bytes()).expect("key")
+    };
+    let mut smt = HashedSmt::default();
+    for i in 0..10 {
+        smt.update(key(i), [i as u8 + 1; 32].into())
+            .expect("update");
+    }
+    // the tree works but can't give proofs for the keys
+    assert_eq!(smt.get(&key(7)).expect("get"), [8u8; 32].into());
+    assert!(smt.validate());
+    assert_eq!(
+        smt.membership_proof(&key(7)).unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+    assert_eq!(
+        smt.non_membership_proof(&key(10)).unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+    assert_eq!(
+        smt.batch_proof(&[key(7), key(10)]).unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+    assert_eq!(
+        proof_ics23::get_tree_spec::<Blake2bHasher, HashedKey<Blake2bHasher>, H256, 32>()
+            .unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+    assert_eq!(
+        proof_ics23::get_prehashed_key_spec::<32>(ics23::HashOp::Sha256, ics23::HashOp::NoHash)
+            .unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+    assert_eq!(
+        proof_ics23::get_domain_separated_prehashed_key_spec::<32>(
+            ics23::HashOp::Sha256,
+            ics23::HashOp::NoHash
+        )
+        .unwrap_err(),
+        Error::UnsupportedPrehash
+    );
+}
+
 fn leaves(
     min_leaves: usize,
     max_leaves: usize,
@@ -427,14 +1864,14 @@ proptest! {
         let one: H256 = [255u8; 32].into();
         let target = one.copy_bits(start..(start.saturating_add(size)));
         for i in start..start.saturating_add(size) {
-            assert_eq!(one.get_bit(i as u8), target.get_bit(i as u8));
+            assert_eq!(one.get_bit(i), target.get_bit(i));
         }
         for i in 0..start {
-            assert!(!target.get_bit(i as u8));
+            assert!(!target.get_bit(i));
         }
         if let Some(start_i) = start.checked_add(size).and_then(|i| i.checked_add(1)){
             for i in start_i..=255 {
-                assert!(!target.get_bit(i as u8));
+                assert!(!target.get_bit(i));
             }
         }
     }
@@ -459,6 +1896,34 @@ proptest! {
         }
     }
 
+    #[test]
+    fn test_internal_key_bit_ops(
+        key: [u8; 32],
+        other: [u8; 32],
+        common in 0usize..=32,
+        start in 0usize..300,
+        size in 0usize..300,
+    ) {
+        // share the first bytes so the fork happens at any height
+        let mut other = other;
+        other[..common].copy_from_slice(&key[..common]);
+        let (key, other) = (InternalKey::<32>::new(key), InternalKey::<32>::new(other));
+
+        let naive_fork = (0..256).rev().find(|&h| key.get_bit(h) != other.get_bit(h)).unwrap_or(0);
+        assert_eq!(key.fork_height(&other), naive_fork);
+        assert_eq!(other.fork_height(&key), naive_fork);
+
+        let end = start + size;
+        let mut naive_copy = InternalKey::<32>::zero();
+        for i in start..end.min(256) {
+            if key.get_bit(i) {
+                naive_copy.set_bit(i);
+            }
+        }
+        assert_eq!(key.copy_bits(start..end), naive_copy);
+        assert_eq!(key.copy_bits(start..), key.copy_bits(start..256));
+    }
+
     #[test]
     fn test_random_update(key: [u8; 31], value: [u8;32]) {
         test_update(key.into(), value.into());
@@ -512,6 +1977,47 @@ proptest! {
         assert!(smt.validate());
     }
 
+    #[test]
+    fn test_smt_compiled_proof_no_alloc((pairs, n) in leaves(1, 50)){
+        let smt = new_smt::<29>(pairs.clone());
+        let mut data: Vec<(PaddedKey<29>, H256)> = pairs.into_iter().take(n).collect();
+        data.sort_by_key(|(k, _v)| **k);
+        let proof = smt.merkle_proof(data.iter().map(|(k, _v)| *k).collect()).expect("gen proof");
+        let compiled_proof = proof.compile(data.clone()).expect("compile proof");
+        let program = &compiled_proof.0;
+        assert_eq!(
+            compute_compiled_root::<Blake2bHasher, PaddedKey<29>, H256, 29, 50>(program, &data),
+            compiled_proof.compute_root::<Blake2bHasher, PaddedKey<29>, H256, 29>(data.clone())
+        );
+        assert!(verify_compiled::<Blake2bHasher, PaddedKey<29>, H256, 29, 50>(program, smt.root(), &data).expect("verify compiled proof"));
+        if data.len() > 1 {
+            let too_small = verify_compiled::<Blake2bHasher, PaddedKey<29>, H256, 29, 1>(program, smt.root(), &data);
+            assert_eq!(too_small, Err(Error::StackOverflow(1)));
+            data.reverse();
+            let unsorted = verify_compiled::<Blake2bHasher, PaddedKey<29>, H256, 29, 50>(program, smt.root(), &data);
+            assert_eq!(unsorted, Err(Error::UnsortedKeys));
+        }
+    }
+
+    #[test]
+    fn test_smt_witness((pairs, n) in leaves(1, 50)){
+        let smt = new_smt::<29>(pairs.clone());
+        let data: Vec<(PaddedKey<29>, H256)> = pairs.into_iter().take(n).collect();
+        let proof = smt.merkle_proof(data.iter().map(|(k, _v)| *k).collect()).expect("gen proof");
+        let witness = witness::Witness::new::<Blake2bHasher, 29>(proof, data.clone()).expect("witness");
+        assert_eq!(witness.root(), smt.root());
+        assert_eq!(witness.leaves().len(), data.len());
+        for leaf in witness.leaves() {
+            assert_eq!(leaf.siblings().len(), 8 * 29);
+            assert_eq!(leaf.directions().len(), 8 * 29);
+            assert_eq!(leaf.compute_root::<Blake2bHasher>(), *smt.root());
+            // the path of a single leaf proof is the same
+            let proof = smt.merkle_proof(vec![*leaf.key()]).expect("gen proof");
+            let single = witness::Witness::new::<Blake2bHasher, 29>(proof, vec![(*leaf.key(), *leaf.value())]).expect("witness");
+            assert_eq!(&single.leaves()[0], leaf);
+        }
+    }
+
     #[test]
     fn test_smt_multi_leaves_large((pairs, _n) in leaves(50, 100)){
         let n = 20;
@@ -557,6 +2063,341 @@ proptest! {
         assert!(compiled_proof.verify::<Blake2bHasher, PaddedKey<29>, H256, 29>(smt.root(), data).expect("verify compiled proof"));
     }
 
+    #[test]
+    fn test_smt_non_membership_merkle_proof((pairs, _n) in leaves(1, 20), (pairs2, _n2) in leaves(1, 5)){
+        let smt = new_smt::<29>(pairs.clone());
+        let (existing_key, _value) = pairs[0];
+        assert_eq!(smt.non_membership_merkle_proof(&existing_key).unwrap_err(), Error::NonExistenceProof);
+        for (k, _v) in pairs2 {
+            let proof = smt.non_membership_merkle_proof(&k).expect("gen proof");
+            assert!(proof.verify::<Blake2bHasher, 29>(smt.root(), &k).expect("verify proof"));
+            assert!(!proof.verify::<Blake2bHasher, 29>(smt.root(), &existing_key).unwrap_or(false));
+            let bytes = borsh::to_vec(&proof).expect("encode proof");
+            let decoded: NonMembershipProof<PaddedKey<29>, H256> = borsh::from_slice(&bytes).expect("decode proof");
+            assert!(decoded.verify::<Blake2bHasher, 29>(smt.root(), &k).expect("verify proof"));
+        }
+    }
+
+    #[test]
+    fn test_smt_range_proof((pairs, n) in leaves(1, 30), (pairs2, n2) in leaves(1, 4)) {
+        let smt = new_smt::<29>(pairs.clone());
+        let mut leaves: Vec<_> = smt.iter().collect::<Result<_, _>>().expect("iter");
+        leaves.sort_by_key(|(k, _v)| **k);
+        // bounds on existing and missing keys
+        let mut bounds: Vec<_> = pairs.iter().chain(pairs2.iter()).map(|(k, _v)| *k).collect();
+        bounds.sort_by_key(|k| **k);
+        let i = (n * 7) % bounds.len();
+        let j = (n2 * 13 + n) % bounds.len();
+        let (start, end) = (bounds[i.min(j)], bounds[i.max(j)]);
+        for (start, end) in [(start, end), (bounds[0], bounds[bounds.len() - 1]), (start, start)] {
+            let proof = smt.range_proof(&start, &end).expect("gen proof");
+            let expected: Vec<_> = leaves.iter().filter(|(k, _v)| *start <= **k && **k < *end).copied().collect();
+            assert_eq!(proof.leaves(), &expected[..]);
+            assert!(proof.verify::<Blake2bHasher, 29>(smt.root(), &start, &end).expect("verify proof"));
+            let bytes = borsh::to_vec(&proof).expect("encode proof");
+            let decoded: RangeProof<PaddedKey<29>, H256> = borsh::from_slice(&bytes).expect("decode proof");
+            assert!(decoded.verify::<Blake2bHasher, 29>(smt.root(), &start, &end).expect("verify proof"));
+
+            // a leaf of the range is missing
+            if !expected.is_empty() {
+                let (left, mut range, right, _proof) = proof.take();
+                range.remove(n % range.len());
+                let keys: Vec<_> = left.iter().chain(range.iter()).chain(right.iter()).map(|(k, _v)| *k).collect();
+                let merkle_proof = if keys.is_empty() {
+                    MerkleProof::new(Vec::new(), Vec::new())
+                } else {
+                    smt.merkle_proof(keys).expect("gen proof")
+                };
+                let forged = RangeProof::new(left, range, right, merkle_proof);
+                assert!(!forged.verify::<Blake2bHasher, 29>(smt.root(), &start, &end).unwrap_or(false));
+            }
+        }
+        assert_eq!(smt.range_proof(&bounds[bounds.len() - 1], &bounds[0]).is_err(), bounds.len() > 1 && *bounds[0] != *bounds[bounds.len() - 1]);
+    }
+
+    #[test]
+    fn test_smt_update_proof((pairs, n) in leaves(1, 20), (pairs2, _n2) in leaves(1, 5)) {
+        let smt = new_smt::<29>(pairs.clone());
+        let old_root = *smt.root();
+        // overwrite and delete some keys and insert new ones
+        let writes: Vec<_> = pairs
+            .iter()
+            .take(n)
+            .map(|(k, v)| (*k, if v.get_bit(0) { H256::zero() } else { [7u8; 32].into() }))
+            .chain(pairs2.iter().copied())
+            .collect();
+        let mut updated = new_smt::<29>(pairs);
+        for (k, v) in writes.iter() {
+            updated.update(*k, *v).expect("update");
+        }
+        let new_root = *updated.root();
+        let proof = smt.update_proof(&writes).expect("gen proof");
+        assert!(proof.verify::<Blake2bHasher, 29>(&old_root, &new_root, &writes).expect("verify proof"));
+        assert!(!proof.verify::<Blake2bHasher, 29>(&old_root, &old_root, &writes).expect("verify proof") || old_root == new_root);
+        let bytes = borsh::to_vec(&proof).expect("encode proof");
+        let decoded: UpdateProof<PaddedKey<29>, H256> = borsh::from_slice(&bytes).expect("decode proof");
+        assert!(decoded.verify::<Blake2bHasher, 29>(&old_root, &new_root, &writes).expect("verify proof"));
+
+        // the missing keys without the leaves next to them
+        let (leaves, _proof) = proof.take();
+        let opened = leaves.len();
+        let written: Vec<_> = leaves
+            .into_iter()
+            .filter(|(k, _v)| writes.iter().any(|(key, _)| key == k))
+            .collect();
+        let merkle_proof = smt.merkle_proof(written.iter().map(|(k, _v)| *k).collect()).expect("gen proof");
+        let forged = UpdateProof::new(written, merkle_proof);
+        if forged.leaves().len() < opened {
+            assert!(!forged.verify::<Blake2bHasher, 29>(&old_root, &new_root, &writes).unwrap_or(false));
+        }
+    }
+
+    #[test]
+    fn test_smt_non_membership_merkle_proof_existing_key((pairs, n) in leaves(3, 30)){
+        let mut pairs = pairs;
+        pairs.sort_by_key(|(k, _v)| **k);
+        pairs.dedup_by_key(|(k, _v)| *k);
+        let smt = new_smt::<29>(pairs.clone());
+        // the proof of the removed key doesn't verify against the tree with the key
+        let i = n % pairs.len();
+        let (key, _value) = pairs[i];
+        let mut without_key = pairs.clone();
+        without_key.remove(i);
+        let smt_without_key = new_smt::<29>(without_key);
+        let proof = smt_without_key.non_membership_merkle_proof(&key).expect("gen proof");
+        assert!(proof.verify::<Blake2bHasher, 29>(smt_without_key.root(), &key).expect("verify proof"));
+        assert!(!proof.verify::<Blake2bHasher, 29>(smt.root(), &key).unwrap_or(false));
+        // leaves around the key with a valid merkle proof are not adjacent
+        let left = i.checked_sub(1).map(|j| pairs[j]);
+        let right = pairs.get(i + 1).copied();
+        let keys = left.iter().chain(right.iter()).map(|(k, _v)| *k).collect();
+        let merkle_proof = smt.merkle_proof(keys).expect("gen proof");
+        let forged = NonMembershipProof::new(left, right, merkle_proof);
+        assert!(!forged.verify::<Blake2bHasher, 29>(smt.root(), &key).unwrap_or(false));
+    }
+
+    #[test]
+    fn test_smt_versioned_reads((pairs, n) in leaves(2, 20), (pairs2, _n2) in leaves(1, 5)) {
+        type VersionedSmt = SparseMerkleTree<
+            Blake2bHasher,
+            PaddedKey<29>,
+            H256,
+            VersionedStore<DefaultStore<PaddedKey<29>, H256, 29>>,
+            29,
+        >;
+        let mut smt = VersionedSmt::default();
+        let mut current = Smt::<29>::default();
+        let keys: Vec<_> = pairs.iter().chain(pairs2.iter()).map(|(k, _v)| *k).collect();
+        // insert, overwrite and delete keys, remember the values at each root
+        let updates: Vec<_> = pairs
+            .iter()
+            .copied()
+            .chain(pairs.iter().take(n).map(|(k, v)| (*k, if v.get_bit(0) { H256::zero() } else { [7u8; 32].into() })))
+            .chain(pairs2.iter().copied())
+            .collect();
+        let mut versions = Vec::new();
+        for (k, v) in updates {
+            smt.update(k, v).expect("update");
+            current.update(k, v).expect("update");
+            assert_eq!(smt.root(), current.root());
+            let values: Vec<_> = keys.iter().map(|k| current.get(k).expect("get")).collect();
+            versions.push((*smt.root(), values));
+        }
+        for (root, values) in versions {
+            let mut data: Vec<_> = keys.iter().copied().zip(values).collect();
+            for (k, v) in data.iter() {
+                assert_eq!(&smt.get_at(&root, k).expect("get at"), v);
+            }
+            data.sort_by_key(|(k, _v)| **k);
+            data.dedup_by_key(|(k, _v)| *k);
+            let proof = smt.merkle_proof_at(&root, data.iter().map(|(k, _v)| *k).collect()).expect("gen proof");
+            assert!(proof.verify::<Blake2bHasher, PaddedKey<29>, H256, 29>(&root, data).expect("verify proof"));
+        }
+    }
+
+    #[test]
+    fn test_smt_versioned_prune((pairs, n) in leaves(2, 20), (pairs2, _n2) in leaves(1, 5)) {
+        type VersionedSmt = SparseMerkleTree<
+            Blake2bHasher,
+            PaddedKey<29>,
+            H256,
+            VersionedStore<DefaultStore<PaddedKey<29>, H256, 29>>,
+            29,
+        >;
+        let mut smt = VersionedSmt::default();
+        let mut current = Smt::<29>::default();
+        let keys: Vec<_> = pairs.iter().chain(pairs2.iter()).map(|(k, _v)| *k).collect();
+        let updates: Vec<_> = pairs
+            .iter()
+            .copied()
+            .chain(pairs.iter().take(n).map(|(k, v)| (*k, if v.get_bit(0) { H256::zero() } else { [7u8; 32].into() })))
+            .chain(pairs2.iter().copied())
+            .collect();
+        let mut versions = Vec::new();
+        for (i, (k, v)) in updates.into_iter().enumerate() {
+            smt.update(k, v).expect("update");
+            current.update(k, v).expect("update");
+            let root = *smt.root();
+            smt.store_mut().commit(&root);
+            // keep every third version and the last one
+            if i % 3 == 0 {
+                let values: Vec<_> = keys.iter().map(|k| current.get(k).expect("get")).collect();
+                versions.push((root, values));
+            }
+        }
+        let values: Vec<_> = keys.iter().map(|k| current.get(k).expect("get")).collect();
+        versions.push((*smt.root(), values));
+        let keep_roots: Vec<_> = versions.iter().map(|(root, _)| *root).collect();
+
+        smt.store_mut().prune::<PaddedKey<29>, H256, 29>(&keep_roots).expect("prune");
+        assert!(smt.store().roots().values().all(|root| keep_roots.contains(root)));
+        for (root, values) in &versions {
+            for (k, v) in keys.iter().zip(values) {
+                assert_eq!(&smt.get_at(root, k).expect("get at"), v);
+            }
+        }
+
+        // the pruning leaves no unreachable leaf to the mark and sweep
+        let leaves = smt.store().store().leaves_map().len();
+        smt.store_mut().gc::<PaddedKey<29>, H256, 29>(&keep_roots).expect("gc");
+        assert_eq!(smt.store().store().leaves_map().len(), leaves);
+        for (root, values) in &versions {
+            for (k, v) in keys.iter().zip(values) {
+                assert_eq!(&smt.get_at(root, k).expect("get at"), v);
+            }
+        }
+
+        // only the last root is kept
+        let root = *smt.root();
+        smt.store_mut().prune::<PaddedKey<29>, H256, 29>(&[root]).expect("prune");
+        let mut latest = current.store().clone();
+        gc(&mut latest, &[root]).expect("gc");
+        assert_eq!(smt.store().store().leaves_map().len(), latest.leaves_map().len());
+        for k in &keys {
+            assert_eq!(smt.get(k), current.get(k));
+        }
+    }
+
+    #[test]
+    fn test_smt_transaction((pairs, n) in leaves(1, 20), (pairs2, _n2) in leaves(1, 10)) {
+        let mut smt = new_smt::<29>(pairs.clone());
+        let mut expected = new_smt::<29>(pairs.clone());
+        let root = *smt.root();
+        let branches = smt.store().branches_map().clone();
+        let leaves = smt.store().leaves_map().clone();
+        // overwrite and delete some keys and insert new ones
+        let updates: Vec<_> = pairs
+            .iter()
+            .take(n)
+            .map(|(k, v)| (*k, if v.get_bit(0) { H256::zero() } else { [7u8; 32].into() }))
+            .chain(pairs2.iter().copied())
+            .collect();
+        for (k, v) in updates.iter() {
+            expected.update(*k, *v).expect("update");
+        }
+        let mut keys: Vec<_> = pairs.iter().chain(pairs2.iter()).map(|(k, _v)| *k).collect();
+        keys.sort_by_key(|k| **k);
+        keys.dedup_by_key(|k| **k);
+        let data: Vec<_> = keys.iter().map(|k| (*k, expected.get(k).expect("get"))).collect();
+
+        // roll back then commit the same updates
+        for commit in [false, true] {
+            let mut tx = smt.begin();
+            for (k, v) in updates.iter() {
+                tx.update(*k, *v).expect("update");
+            }
+            assert_eq!(tx.root(), expected.root());
+            for (k, v) in data.iter() {
+                assert_eq!(&tx.get(k).expect("get"), v);
+            }
+            let proof = tx.merkle_proof(keys.clone()).expect("gen proof");
+            assert!(proof.verify::<Blake2bHasher, PaddedKey<29>, H256, 29>(tx.root(), data.clone()).expect("verify proof"));
+            if commit {
+                tx.commit().expect("commit");
+            } else {
+                tx.rollback();
+                assert_eq!(smt.root(), &root);
+                assert_eq!(smt.store().branches_map(), &branches);
+                assert_eq!(smt.store().leaves_map(), &leaves);
+            }
+        }
+        assert_eq!(smt.root(), expected.root());
+        assert_eq!(smt.store().branches_map(), expected.store().branches_map());
+        assert_eq!(smt.store().leaves_map(), expected.store().leaves_map());
+    }
+
+    #[test]
+    fn test_smt_diff((pairs, n) in leaves(1, 20), (pairs2, _n2) in leaves(1, 10)) {
+        type VersionedSmt = SparseMerkleTree<
+            Blake2bHasher,
+            PaddedKey<29>,
+            H256,
+            VersionedStore<DefaultStore<PaddedKey<29>, H256, 29>>,
+            29,
+        >;
+        let mut smt = VersionedSmt::default();
+        smt.update_all(pairs.clone()).expect("update");
+        let old_root = *smt.root();
+        let mut keys: Vec<_> = pairs.iter().chain(pairs2.iter()).map(|(k, _v)| *k).collect();
+        keys.sort_by_key(|k| **k);
+        keys.dedup_by_key(|k| **k);
+        let old_values: Vec<_> = keys.iter().map(|k| smt.get(k).expect("get")).collect();
+        // overwrite and delete some keys and insert new ones
+        for (k, v) in pairs.iter().take(n) {
+            smt.update(*k, if v.get_bit(0) { H256::zero() } else { [7u8; 32].into() }).expect("update");
+        }
+        smt.update_all(pairs2.clone()).expect("update");
+        let new_root = *smt.root();
+        let new_values: Vec<_> = keys.iter().map(|k| smt.get(k).expect("get")).collect();
+
+        let changes: Vec<_> = keys
+            .iter()
+            .zip(old_values.iter().zip(new_values.iter()))
+            .filter(|(_k, (old, new))| old != new)
+            .map(|(k, (old, new))| match (old.is_zero(), new.is_zero()) {
+                (true, _) => Change::Added(*k, *new),
+                (_, true) => Change::Removed(*k, *old),
+                _ => Change::Modified { key: *k, old: *old, new: *new },
+            })
+            .collect();
+        let diff: Vec<_> = smt.diff(&old_root, &new_root).collect::<Result<_, _>>().expect("diff");
+        assert_eq!(diff, changes);
+        let reverted: Vec<_> = changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Added(k, v) => Change::Removed(k, v),
+                Change::Removed(k, v) => Change::Added(k, v),
+                Change::Modified { key, old, new } => Change::Modified { key, old: new, new: old },
+            })
+            .collect();
+        let diff: Vec<_> = smt.diff(&new_root, &old_root).collect::<Result<_, _>>().expect("diff");
+        assert_eq!(diff, reverted);
+        assert_eq!(smt.diff(&new_root, &new_root).count(), 0);
+        let diff: Vec<_> = smt.diff(&H256::zero(), &new_root).collect::<Result<_, _>>().expect("diff");
+        let leaves: Vec<_> = smt.iter().map(|leaf| leaf.map(|(k, v)| Change::Added(k, v))).collect::<Result<_, _>>().expect("iter");
+        assert_eq!(diff, leaves);
+    }
+
+    #[test]
+    fn test_smt_snapshot((pairs, _n) in leaves(1, 30), chunk_size in 1usize..8) {
+        let smt = new_smt::<29>(pairs);
+        let root = *smt.root();
+        let mut restorer = SnapshotRestorer::<Blake2bHasher, _, _, DefaultStore<PaddedKey<29>, H256, 29>, 29>::new(root, Default::default());
+        let mut count = 0;
+        for chunk in smt.snapshot(&root, chunk_size) {
+            let chunk = chunk.expect("export chunk");
+            assert!(chunk.leaves().len() <= chunk_size);
+            assert!(!restorer.is_complete());
+            restorer.restore(chunk).expect("restore chunk");
+            count += 1;
+        }
+        let size = smt.store().leaves_map().len();
+        assert_eq!(count, size.div_ceil(chunk_size));
+        let restored = restorer.finish().expect("finish");
+        assert_eq!(restored.root(), &root);
+        assert_eq!(restored.store().leaves_map(), smt.store().leaves_map());
+    }
+
     #[test]
     fn test_update_smt_tree_store((pairs, n) in leaves(1, 20)) {
         let smt = new_smt::<29>(pairs.clone());
@@ -603,6 +2444,115 @@ proptest! {
         }
     }
 
+    #[test]
+    fn test_smt_update_all((pairs, n) in leaves(1, 50), (pairs2, _n2) in leaves(1, 20)){
+        let mut smt = new_smt::<29>(pairs[..n].to_vec());
+        let mut batch_smt = new_smt::<29>(pairs[..n].to_vec());
+        // insert new keys, overwrite and delete existing keys
+        let mut updates: Vec<(PaddedKey<29>, H256)> = pairs2;
+        updates.extend(pairs.iter().skip(n / 2).map(|(k, _v)| (*k, [7u8; 32].into())));
+        updates.extend(pairs.iter().step_by(3).map(|(k, _v)| (*k, H256::zero())));
+        updates.shuffle(&mut rand::thread_rng());
+
+        for (k, v) in updates.clone() {
+            smt.update(k, v).expect("update");
+        }
+        batch_smt.update_all(updates.clone()).expect("update all");
+
+        assert_eq!(smt.root(), batch_smt.root());
+        assert_eq!(smt.store().leaves_map(), batch_smt.store().leaves_map());
+        let mut branches: Vec<_> = smt.store().branches_map().keys().collect();
+        let mut batch_branches: Vec<_> = batch_smt.store().branches_map().keys().collect();
+        branches.sort_unstable();
+        batch_branches.sort_unstable();
+        assert_eq!(branches, batch_branches);
+        assert!(batch_smt.validate());
+        for (k, _v) in pairs.iter().chain(updates.iter()) {
+            assert_eq!(smt.get(k), batch_smt.get(k));
+        }
+    }
+
+    #[test]
+    fn test_smt_update_all_dense_keys(
+        pairs in prop::collection::vec((any::<u8>(), any::<bool>()), 1..64),
+        updates in prop::collection::vec((any::<u8>(), any::<bool>()), 1..64),
+    ){
+        // one byte keys fork at every height including zero
+        let to_leaf = |(k, delete): (u8, bool)| {
+            let value = if delete { H256::zero() } else { [k; 32].into() };
+            (PaddedKey::<1>::from([k]), value)
+        };
+        let pairs: Vec<_> = pairs.into_iter().map(to_leaf).collect();
+        let updates: Vec<_> = updates.into_iter().map(to_leaf).collect();
+        let mut smt = new_smt::<1>(pairs.clone());
+        let mut batch_smt = new_smt::<1>(pairs);
+        for (k, v) in updates.clone() {
+            smt.update(k, v).expect("update");
+        }
+        batch_smt.update_all(updates).expect("update all");
+        assert_eq!(smt.root(), batch_smt.root());
+        assert_eq!(smt.store().leaves_map(), batch_smt.store().leaves_map());
+        assert!(batch_smt.validate());
+        for k in 0..=255u8 {
+            assert_eq!(smt.get(&[k].into()), batch_smt.get(&[k].into()));
+        }
+    }
+
+    #[test]
+    fn test_smt_from_sorted_leaves((pairs, _n) in leaves(0, 100)){
+        let mut pairs = pairs;
+        pairs.sort_by_key(|(k, _v)| **k);
+        pairs.dedup_by_key(|(k, _v)| *k);
+        let smt = new_smt::<29>(pairs.clone());
+        let built_smt = Smt::<29>::from_sorted_leaves(pairs, DefaultStore::default())
+            .expect("build tree");
+        assert_eq!(smt.root(), built_smt.root());
+        assert_eq!(smt.store().leaves_map(), built_smt.store().leaves_map());
+        assert_eq!(smt.store().branches_map(), built_smt.store().branches_map());
+        assert!(built_smt.validate());
+    }
+
+    #[test]
+    fn test_smt_iter_range((pairs, n) in leaves(0, 50), bounds in (any::<usize>(), any::<usize>())){
+        let mut pairs = pairs;
+        pairs.sort_by_key(|(k, _v)| **k);
+        pairs.dedup_by_key(|(k, _v)| *k);
+        let smt = new_smt::<29>(pairs.clone());
+        let all: Vec<_> = smt.iter().collect::<Result<_, _>>().expect("iter");
+        assert_eq!(all, pairs);
+        if !pairs.is_empty() {
+            let (a, b) = (bounds.0 % pairs.len(), bounds.1 % pairs.len());
+            let (start, end) = (pairs[a.min(b)].0, pairs[a.max(b)].0);
+            let range: Vec<_> = smt.range(start..end).collect::<Result<_, _>>().expect("range");
+            assert_eq!(range, pairs[a.min(b)..a.max(b)].to_vec());
+            let range: Vec<_> = smt.range(..=end).collect::<Result<_, _>>().expect("range");
+            assert_eq!(range, pairs[..=a.max(b)].to_vec());
+            let range: Vec<_> = smt.range(start..).take(n).collect::<Result<_, _>>().expect("range");
+            assert_eq!(range, pairs[a.min(b)..].iter().take(n).cloned().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_smt_proof_encoding((pairs, n) in leaves(1, 50)){
+        let smt = new_smt::<29>(pairs.clone());
+        let proof = smt.merkle_proof(pairs.iter().take(n).map(|(k, _v)| *k).collect()).expect("gen proof");
+        let data: Vec<(PaddedKey<29>, H256)> = pairs.into_iter().take(n).collect();
+        let compiled_proof = proof.clone().compile(data.clone()).expect("compile proof");
+        assert!(proof.check::<29>().is_ok());
+        assert!(compiled_proof.check::<29>().is_ok());
+
+        let bytes = borsh::to_vec(&proof).expect("encode proof");
+        let decoded: MerkleProof = borsh::from_slice(&bytes).expect("decode proof");
+        assert_eq!(decoded.leaves_path(), proof.leaves_path());
+        assert_eq!(decoded.proof(), proof.proof());
+        assert!(decoded.verify::<Blake2bHasher, PaddedKey<29>, H256, 29>(smt.root(), data.clone()).expect("verify proof"));
+
+        let bytes = borsh::to_vec(&compiled_proof).expect("encode compiled proof");
+        let decoded: CompiledMerkleProof = borsh::from_slice(&bytes).expect("decode compiled proof");
+        assert_eq!(decoded.0, compiled_proof.0);
+        assert!(decoded.verify::<Blake2bHasher, PaddedKey<29>, H256, 29>(smt.root(), data).expect("verify compiled proof"));
+    }
+
     #[test]
     fn test_ics23_proof_single_leaf_small((pairs, _n) in leaves(1, 50)){
         let pairs: Vec<(PaddedKey<120>, H256)> = pairs
@@ -610,7 +2560,7 @@ proptest! {
         .map(|(key, v)| (PaddedKey::<120>::try_from(<[u8; 29]>::from(key).to_vec()).expect("Test failed"), v))
         .collect();
         let smt = new_sha_smt::<120>(pairs.clone());
-        let spec = proof_ics23::get_spec(ics23::HashOp::Sha256);
+        let spec = proof_ics23::get_spec::<120>(ics23::HashOp::Sha256);
         let root = smt.root().as_slice().to_vec();
         for (k, v) in pairs {
             let proof = smt.membership_proof(&k).expect("gen proof");
@@ -629,7 +2579,7 @@ proptest! {
         .filter_map(|(key, v)| PaddedKey::<115>::try_from(<[u8; 29]>::from(key).to_vec()).ok().map(|k| (k, v)))
         .collect();
         let smt = new_sha_smt::<115>(pairs.clone());
-        let spec = proof_ics23::get_spec(ics23::HashOp::Sha256);
+        let spec = proof_ics23::get_spec::<115>(ics23::HashOp::Sha256);
         let root = smt.root().as_slice().to_vec();
         let exists_key: Vec<_> = pairs.into_iter().map(|(k, _v)|k).collect();
         let non_exists_keys: Vec<_> = pairs2.into_iter().map(|(k, _v)|k).filter(|k| !exists_key.contains(k)).collect();
@@ -638,6 +2588,65 @@ proptest! {
             assert!(ics23::verify_non_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, &k.to_vec()));
         }
     }
+
+    #[test]
+    fn test_domain_separated_proofs((pairs, n) in leaves(1, 30), (pairs2, _n2) in leaves(1, 10)) {
+        let mut smt = DomainSeparatedSmt::<29>::default();
+        smt.update_all(pairs.clone()).expect("update all");
+        assert!(smt.validate());
+        let mut sorted = pairs.clone();
+        sorted.sort_by_key(|(k, _v)| **k);
+        sorted.dedup_by_key(|(k, _v)| *k);
+        let from_sorted = DomainSeparatedSmt::<29>::from_sorted_leaves(sorted, Default::default()).expect("from sorted leaves");
+        assert_eq!(from_sorted.root(), smt.root());
+
+        let data: Vec<(PaddedKey<29>, H256)> = pairs.iter().take(n).copied().collect();
+        let proof = smt.merkle_proof(data.iter().map(|(k, _v)| *k).collect()).expect("gen proof");
+        let compiled_proof = proof.clone().compile(data.clone()).expect("compile proof");
+        assert!(proof.verify::<DomainSeparated<Sha256Hasher>, PaddedKey<29>, H256, 29>(smt.root(), data.clone()).expect("verify proof"));
+        assert!(compiled_proof.verify::<DomainSeparated<Sha256Hasher>, PaddedKey<29>, H256, 29>(smt.root(), data.clone()).expect("verify compiled proof"));
+        assert!(!compiled_proof.verify::<Sha256Hasher, PaddedKey<29>, H256, 29>(smt.root(), data).expect("verify compiled proof"));
+
+        let spec = proof_ics23::get_domain_separated_spec::<29>(ics23::HashOp::Sha256);
+        let root = smt.root().as_slice().to_vec();
+        for (k, v) in pairs.iter().take(n) {
+            let proof = smt.membership_proof(k).expect("gen proof");
+            assert!(ics23::verify_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, &k.to_vec(), v.as_slice()));
+        }
+        let exists_keys: Vec<_> = pairs.iter().map(|(k, _v)| *k).collect();
+        for (k, _v) in pairs2.iter().filter(|(k, _v)| !exists_keys.contains(k)) {
+            let proof = smt.non_membership_proof(k).expect("gen proof");
+            assert!(ics23::verify_non_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, &k.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_ics23_batch_proof((pairs, n) in leaves(1, 30), (pairs2, _n2) in leaves(1, 10)) {
+        let smt = new_sha_smt::<29>(pairs.clone());
+        let spec = proof_ics23::get_spec::<29>(ics23::HashOp::Sha256);
+        let root = smt.root().as_slice().to_vec();
+        let exists: Vec<(Vec<u8>, Vec<u8>)> = pairs
+            .iter()
+            .take(n)
+            .map(|(k, v)| (k.to_vec(), v.as_slice().to_vec()))
+            .collect();
+        let exists_keys: Vec<_> = pairs.iter().map(|(k, _v)| *k).collect();
+        let non_exists_keys: Vec<_> = pairs2.into_iter().map(|(k, _v)| k).filter(|k| !exists_keys.contains(k)).collect();
+        let keys: Vec<_> = exists_keys.iter().take(n).chain(non_exists_keys.iter()).copied().collect();
+        let items = exists.iter().map(|(k, v)| (k.as_slice(), v.as_slice())).collect();
+        let non_exists: Vec<Vec<u8>> = non_exists_keys.iter().map(|k| k.to_vec()).collect();
+        let non_exists: Vec<&[u8]> = non_exists.iter().map(|k| k.as_slice()).collect();
+
+        let proof = smt.batch_proof(&keys).expect("gen batch proof");
+        assert!(ics23::verify_batch_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, items));
+        assert!(ics23::verify_batch_non_membership::<ics23::HostFunctionsManager>(&proof, &spec, &root, &non_exists));
+
+        let compressed = smt.compressed_batch_proof(&keys).expect("gen compressed batch proof");
+        assert!(ics23::is_compressed(&compressed));
+        let items = exists.iter().map(|(k, v)| (k.as_slice(), v.as_slice())).collect();
+        assert!(ics23::verify_batch_membership::<ics23::HostFunctionsManager>(&compressed, &spec, &root, items));
+        assert!(ics23::verify_batch_non_membership::<ics23::HostFunctionsManager>(&compressed, &spec, &root, &non_exists));
+    }
 }
 
 #[test]
@@ -679,7 +2688,7 @@ fn test_v0_2_broken_sample() {
     ]
     .into_iter()
     .map(parse_h256);
-    let mut pairs = keys.into_iter().zip(values.into_iter()).collect::<Vec<_>>();
+    let mut pairs = keys.into_iter().zip(values).collect::<Vec<_>>();
     let smt = new_smt::<32>(pairs.clone());
     let base_root = *smt.root();
 
@@ -730,3 +2739,259 @@ fn test_v0_3_broken_sample() {
     smt.update(k3.into(), v3.into()).unwrap();
     assert_eq!(smt.get(&k1.into()).unwrap(), v1.into());
 }
+
+#[test]
+fn test_snapshot_chunk_omitting_a_leaf() {
+    let pairs: Vec<(PaddedKey<1>, H256)> = [0x00u8, 0x04, 0x06, 0xF0]
+        .iter()
+        .map(|i| ([*i].into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_smt::<1>(pairs.clone());
+    let root = *smt.root();
+    let chunks: Vec<_> = smt
+        .snapshot(&root, 1)
+        .collect::<Result<_, _>>()
+        .expect("export");
+    assert_eq!(chunks.len(), 4);
+    assert!(chunks[1].verify::<Blake2bHasher, 1>(&root).expect("verify"));
+
+    // the chunk after 0x00 skips 0x04, the merge of 0x04 in the leaf path
+    // of 0x06 is moved to the gap after the chunk
+    let proof = smt
+        .merkle_proof(vec![pairs[0].0, pairs[2].0])
+        .expect("gen proof");
+    let (mut leaves_path, nodes) = proof.take();
+    assert_eq!(leaves_path, vec![vec![2, 7], vec![1, 2]]);
+    leaves_path[1][0] = 0;
+    let forged = SnapshotChunk::new(
+        1,
+        Some(pairs[0]),
+        vec![pairs[2]],
+        false,
+        MerkleProof::new(leaves_path, nodes),
+    );
+    assert!(!matches!(
+        forged.verify::<Blake2bHasher, 1>(&root),
+        Ok(true)
+    ));
+    let mut restoring = SnapshotRestorer::<
+        Blake2bHasher,
+        PaddedKey<1>,
+        H256,
+        DefaultStore<PaddedKey<1>, H256, 1>,
+        1,
+    >::new(root, Default::default());
+    restoring.restore(chunks[0].clone()).expect("restore");
+    assert_eq!(restoring.restore(forged), Err(Error::InvalidChunk(1)));
+}
+
+#[test]
+fn test_snapshot_rejects_inconsistent_chunks() {
+    let pairs: Vec<_> = (0u8..20)
+        .map(|i| {
+            let mut key = [0u8; 32];
+            key[0] = i.wrapping_mul(37);
+            key[31] = i;
+            (H256::from(key).into(), H256::from([i + 1; 32]))
+        })
+        .collect();
+    let smt = new_smt::<32>(pairs);
+    let root = *smt.root();
+    let chunks: Vec<_> = smt
+        .snapshot(&root, 4)
+        .collect::<Result<_, _>>()
+        .expect("export");
+    assert_eq!(chunks.len(), 5);
+    let restorer = || {
+        SnapshotRestorer::<
+            Blake2bHasher,
+            PaddedKey<32>,
+            H256,
+            DefaultStore<PaddedKey<32>, H256, 32>,
+            32,
+        >::new(root, Default::default())
+    };
+
+    // a modified value
+    let mut leaves = chunks[1].leaves().to_vec();
+    leaves[2].1 = H256::from([0xAA; 32]);
+    let forged = SnapshotChunk::new(
+        1,
+        chunks[1].previous().cloned(),
+        leaves,
+        false,
+        chunks[1].merkle_proof().clone(),
+    );
+    assert!(!forged.verify::<Blake2bHasher, 32>(&root).expect("verify"));
+
+    // an omitted leaf with a valid merkle proof of the other leaves
+    let mut leaves = chunks[1].leaves().to_vec();
+    leaves.remove(2);
+    let keys = chunks[1]
+        .previous()
+        .iter()
+        .map(|(k, _v)| *k)
+        .chain(leaves.iter().map(|(k, _v)| *k))
+        .collect();
+    let proof = smt.merkle_proof(keys).expect("gen proof");
+    let forged = SnapshotChunk::new(1, chunks[1].previous().cloned(), leaves, false, proof);
+    assert!(!forged.verify::<Blake2bHasher, 32>(&root).expect("verify"));
+    let mut restoring = restorer();
+    restoring.restore(chunks[0].clone()).expect("restore");
+    assert_eq!(restoring.restore(forged), Err(Error::InvalidChunk(1)));
+
+    // a truncated snapshot claiming its end
+    let last = &chunks[3];
+    let forged = SnapshotChunk::new(
+        3,
+        last.previous().cloned(),
+        last.leaves().to_vec(),
+        true,
+        last.merkle_proof().clone(),
+    );
+    assert!(!forged.verify::<Blake2bHasher, 32>(&root).expect("verify"));
+
+    // out of order chunks and missing chunks
+    let mut restoring = restorer();
+    assert_eq!(
+        restoring.restore(chunks[1].clone()),
+        Err(Error::InvalidChunk(1))
+    );
+    restoring.restore(chunks[0].clone()).expect("restore");
+    assert_eq!(
+        restoring.restore(chunks[2].clone()),
+        Err(Error::InvalidChunk(2))
+    );
+    restoring.restore(chunks[1].clone()).expect("restore");
+    assert!(matches!(restoring.finish(), Err(Error::IncompleteSnapshot)));
+
+    // another root
+    let mut restoring = SnapshotRestorer::<
+        Blake2bHasher,
+        PaddedKey<32>,
+        H256,
+        DefaultStore<PaddedKey<32>, H256, 32>,
+        32,
+    >::new(H256::from([1u8; 32]), Default::default());
+    assert_eq!(
+        restoring.restore(chunks[0].clone()),
+        Err(Error::InvalidChunk(0))
+    );
+
+    // an empty tree has a single empty chunk
+    let empty = new_smt::<32>(Vec::new());
+    let chunks: Vec<_> = empty
+        .snapshot(&H256::zero(), 4)
+        .collect::<Result<_, _>>()
+        .expect("export");
+    assert_eq!(chunks.len(), 1);
+    let mut restoring = SnapshotRestorer::<
+        Blake2bHasher,
+        PaddedKey<32>,
+        H256,
+        DefaultStore<PaddedKey<32>, H256, 32>,
+        32,
+    >::new(H256::zero(), Default::default());
+    restoring.restore(chunks[0].clone()).expect("restore");
+    assert!(restoring.finish().expect("finish").is_empty());
+}
+
+#[test]
+fn test_range_proof_rejects_tampered_path() {
+    let pairs: Vec<(PaddedKey<1>, H256)> = [0x00u8, 0x04, 0x06]
+        .iter()
+        .map(|i| ([*i].into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_smt::<1>(pairs.clone());
+    let (start, end): (PaddedKey<1>, PaddedKey<1>) = ([0x01].into(), [0x06].into());
+    let proof = smt.range_proof(&start, &end).expect("gen proof");
+    assert_eq!(proof.leaves(), &[pairs[1]]);
+    assert!(proof
+        .verify::<Blake2bHasher, 1>(smt.root(), &start, &end)
+        .expect("verify proof"));
+
+    // no leaf between the neighbors 0x00 and 0x06, the merge of 0x04 in the
+    // leaf path of 0x06 is moved to the gap after it
+    let merkle_proof = smt
+        .merkle_proof(vec![pairs[0].0, pairs[2].0])
+        .expect("gen proof");
+    let (mut leaves_path, nodes) = merkle_proof.take();
+    assert_eq!(leaves_path, vec![vec![2], vec![1, 2]]);
+    leaves_path[1][0] = 0;
+    let forged = RangeProof::new(
+        Some(pairs[0]),
+        Vec::new(),
+        Some(pairs[2]),
+        MerkleProof::new(leaves_path, nodes),
+    );
+    assert!(!matches!(
+        forged.verify::<Blake2bHasher, 1>(smt.root(), &start, &end),
+        Ok(true)
+    ));
+}
+
+#[test]
+fn test_update_proof_rejects_tampered_path() {
+    let pairs: Vec<(PaddedKey<1>, H256)> = [0x00u8, 0x04, 0x06]
+        .iter()
+        .map(|i| ([*i].into(), [42u8; 32].into()))
+        .collect();
+    let smt = new_smt::<1>(pairs.clone());
+    let old_root = *smt.root();
+    let missing: PaddedKey<1> = [0x02].into();
+    let writes = vec![(missing, H256::from([7u8; 32]))];
+    let mut updated = new_smt::<1>(pairs.clone());
+    updated.update(missing, writes[0].1).expect("update");
+    let proof = smt.update_proof(&writes).expect("gen proof");
+    assert!(proof
+        .verify::<Blake2bHasher, 1>(&old_root, updated.root(), &writes)
+        .expect("verify proof"));
+
+    // 0x06 as the leaf after the missing key, the merge of 0x04 in the leaf
+    // path of 0x06 is moved to the gap after it
+    let leaves = vec![pairs[0], (missing, H256::zero()), pairs[2]];
+    let merkle_proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("gen proof");
+    let (mut leaves_path, nodes) = merkle_proof.take();
+    assert_eq!(leaves_path, vec![vec![1, 2], vec![1], vec![1, 2]]);
+    leaves_path[2][0] = 0;
+    let forged = UpdateProof::new(leaves, MerkleProof::new(leaves_path, nodes));
+    assert!(!matches!(
+        forged.verify::<Blake2bHasher, 1>(&old_root, updated.root(), &writes),
+        Ok(true)
+    ));
+}
+
+#[test]
+fn test_update_proof_rejects_hidden_leaf() {
+    let key: PaddedKey<32> = H256::from([1u8; 32]).into();
+    let smt = new_smt::<32>(vec![(key, [2u8; 32].into())]);
+    let root = *smt.root();
+    let writes = vec![(key, H256::from([3u8; 32]))];
+    let proof = smt.update_proof(&writes).expect("gen proof");
+    let mut updated = new_smt::<32>(vec![(key, [2u8; 32].into())]);
+    updated.update(key, [3u8; 32].into()).expect("update");
+    assert!(proof
+        .verify::<Blake2bHasher, 32>(&root, updated.root(), &writes)
+        .expect("verify proof"));
+
+    // the existing leaf passed as a proof node next to the key claimed missing
+    let merkle_proof = MerkleProof::new(vec![vec![255]], vec![(root, 255)]);
+    let forged = UpdateProof::new(vec![(key, H256::zero())], merkle_proof);
+    let (leaves, merkle_proof) = forged.clone().take();
+    assert_eq!(
+        merkle_proof
+            .clone()
+            .compute_root::<Blake2bHasher, PaddedKey<32>, H256, 32>(leaves),
+        Ok(root)
+    );
+    // the root of a tree with two leaves of the key
+    let bogus_root = merkle_proof
+        .compute_root::<Blake2bHasher, PaddedKey<32>, H256, 32>(writes.clone())
+        .expect("compute root");
+    assert_ne!(&bogus_root, updated.root());
+    assert!(!forged
+        .verify::<Blake2bHasher, 32>(&root, &bogus_root, &writes)
+        .unwrap_or(false));
+}
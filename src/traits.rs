@@ -1,6 +1,8 @@
 use crate::{
+    boxed::Box,
     error::Error,
     tree::{BranchNode, LeafNode},
+    vec::Vec,
     Hash as KeyHash, InternalKey, H256,
 };
 use core::hash::Hash;
@@ -13,6 +15,11 @@ pub trait Hasher {
     fn hash_op() -> ics23::HashOp {
         ics23::HashOp::NoHash
     }
+    /// Hash leaves and branches with distinct prefixes and mix the height into
+    /// the branches, see `merge::DomainSeparated`
+    fn is_domain_separated() -> bool {
+        false
+    }
 }
 
 /// This trait is map keys to / from the users key space into a finite
@@ -34,6 +41,20 @@ pub trait Key<const N: usize>:
     /// This should map from the user's key space into
     /// the internal keyspace
     fn try_from_bytes(bytes: &[u8]) -> Result<Self, Self::Error>;
+    /// Whether the internal key is the hash of the user's key
+    ///
+    /// The leaves of prehashed keys commit to the internal key instead of the
+    /// user's key, see `hashed_key::HashedKey`
+    fn is_prehashed() -> bool {
+        Self::prehash_op().is_some()
+    }
+    /// The ICS23 hash of the user's key which gives the internal key, if any
+    ///
+    /// The ICS23 proofs of prehashed keys hash the key the same way as the
+    /// tree, a prehashed key without it can't be proven
+    fn prehash_op() -> Option<ics23::HashOp> {
+        None
+    }
 }
 
 impl Key<32> for KeyHash {
@@ -44,7 +65,7 @@ impl Key<32> for KeyHash {
     }
 
     fn try_from_bytes(bytes: &[u8]) -> Result<Self, Self::Error> {
-        use std::convert::TryInto;
+        use core::convert::TryInto;
         let bytes: [u8; 32] = bytes
             .try_into()
             .map_err(|_| crate::error::Error::KeyTooLarge)?;
@@ -59,6 +80,16 @@ pub trait Value: PartialEq + Clone {
     fn is_zero(&self) -> bool {
         self == &Self::zero()
     }
+    /// Whether the leaves commit to the hash of the value instead of its bytes
+    ///
+    /// A leaf hashes the bytes of its key then of its value without a length,
+    /// so with keys of variable length, values of variable length must be
+    /// prehashed by the hasher of the tree for a leaf to have a single key and
+    /// value. The ICS23 proofs hash them as `LeafOp.prehash_value`, see
+    /// `proof_ics23::get_tree_spec`.
+    fn is_prehashed() -> bool {
+        false
+    }
 }
 
 impl Value for H256 {
@@ -70,6 +101,42 @@ impl Value for H256 {
     }
 }
 
+/// Raw bytes of any length, the empty bytes are the zero value
+///
+/// The leaves commit to the hash of the bytes, see `Value::is_prehashed`
+impl Value for Vec<u8> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+    fn zero() -> Self {
+        Vec::new()
+    }
+    fn is_zero(&self) -> bool {
+        self.is_empty()
+    }
+    fn is_prehashed() -> bool {
+        true
+    }
+}
+
+/// Raw bytes of any length, the empty bytes are the zero value
+///
+/// The leaves commit to the hash of the bytes, like `Vec<u8>`
+impl Value for Box<[u8]> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+    fn zero() -> Self {
+        Box::default()
+    }
+    fn is_zero(&self) -> bool {
+        self.is_empty()
+    }
+    fn is_prehashed() -> bool {
+        true
+    }
+}
+
 /// Trait for customize backend storage
 pub trait Store<K, V, const N: usize>: Default
 where
@@ -85,3 +152,23 @@ where
     fn size(&self) -> usize;
 }
 
+/// Trait for backend storages which can list their nodes, to collect the
+/// nodes which aren't reachable from the roots in use
+pub trait IterableStore<K, V, const N: usize>: Store<K, V, N>
+where
+    K: Key<N>,
+{
+    fn branch_hashes(&self) -> impl Iterator<Item = H256> + '_;
+    fn leaf_hashes(&self) -> impl Iterator<Item = H256> + '_;
+}
+
+/// Trait for storages of the values which leaves commit to by digest, see
+/// `blob_store::BlobTree`
+///
+/// Identical values share a digest, a blob is inserted once for each leaf
+/// committing to it and must be kept until it's removed as many times.
+pub trait BlobStore: Default {
+    fn get_blob(&self, digest: &H256) -> Result<Option<Vec<u8>>, Error>;
+    fn insert_blob(&mut self, digest: H256, blob: Vec<u8>) -> Result<(), Error>;
+    fn remove_blob(&mut self, digest: &H256) -> Result<(), Error>;
+}
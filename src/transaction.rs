@@ -0,0 +1,345 @@
+use crate::{
+    default_store::Map,
+    error::Result,
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::{BranchNode, LeafNode, SparseMerkleTree},
+    vec::Vec,
+    Key, H256,
+};
+use core::ops::Deref;
+use itertools::Itertools;
+
+/// Changes staged by a transaction, None for a node removed from the store
+#[derive(Debug, Clone)]
+struct Staged<K, V, const N: usize>
+where
+    K: Key<N>,
+{
+    branches: Map<H256, Option<BranchNode<K, N>>>,
+    leaves: Map<H256, Option<LeafNode<K, V, N>>>,
+}
+
+impl<K, V, const N: usize> Default for Staged<K, V, N>
+where
+    K: Key<N>,
+{
+    fn default() -> Self {
+        Self {
+            branches: Map::new(),
+            leaves: Map::new(),
+        }
+    }
+}
+
+impl<K, V, const N: usize> Staged<K, V, N>
+where
+    K: Key<N>,
+{
+    /// stage the changes of an update
+    fn apply(&mut self, changes: Changes<K, V, N>) {
+        for (node, change) in changes.branches {
+            change.apply(&mut self.branches, node);
+        }
+        for (leaf_hash, change) in changes.leaves {
+            change.apply(&mut self.leaves, leaf_hash);
+        }
+    }
+}
+
+/// Change of a node by an update, over the changes staged before it
+#[derive(Debug, Clone)]
+enum Change<T> {
+    Insert(T),
+    /// the node is in the backing store, its removal is staged
+    Remove,
+    /// the node is only staged, it is dropped from the staged changes
+    Unstage,
+}
+
+impl<T> Change<T> {
+    fn apply(self, staged: &mut Map<H256, Option<T>>, node: H256) {
+        match self {
+            Change::Insert(value) => {
+                staged.insert(node, Some(value));
+            }
+            Change::Remove => {
+                staged.insert(node, None);
+            }
+            Change::Unstage => {
+                staged.remove(&node);
+            }
+        }
+    }
+
+    fn get(&self) -> Option<&T> {
+        match self {
+            Change::Insert(value) => Some(value),
+            Change::Remove | Change::Unstage => None,
+        }
+    }
+}
+
+/// Changes of a single update, which are staged once it succeeds
+#[derive(Debug)]
+struct Changes<K, V, const N: usize>
+where
+    K: Key<N>,
+{
+    branches: Map<H256, Change<BranchNode<K, N>>>,
+    leaves: Map<H256, Change<LeafNode<K, V, N>>>,
+}
+
+impl<K, V, const N: usize> Default for Changes<K, V, N>
+where
+    K: Key<N>,
+{
+    fn default() -> Self {
+        Self {
+            branches: Map::new(),
+            leaves: Map::new(),
+        }
+    }
+}
+
+/// Store reading the changes of an update over the staged changes over the
+/// backing store
+///
+/// The staged changes are only read, the writes of the update are kept apart
+/// so a failed update leaves the staged changes as they were.
+#[derive(Debug)]
+struct OverlayStore<'a, K, V, S, const N: usize>
+where
+    K: Key<N>,
+{
+    store: Option<&'a S>,
+    staged: Option<&'a Staged<K, V, N>>,
+    changes: Changes<K, V, N>,
+}
+
+impl<'a, K, V, S, const N: usize> Default for OverlayStore<'a, K, V, S, N>
+where
+    K: Key<N>,
+{
+    fn default() -> Self {
+        Self {
+            store: None,
+            staged: None,
+            changes: Changes::default(),
+        }
+    }
+}
+
+impl<'a, K, V, S, const N: usize> Store<K, V, N> for OverlayStore<'a, K, V, S, N>
+where
+    K: Key<N>,
+    V: Clone,
+    S: Store<K, V, N>,
+{
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode<K, N>>> {
+        if let Some(change) = self.changes.branches.get(node) {
+            return Ok(change.get().cloned());
+        }
+        let staged = self.staged.and_then(|staged| staged.branches.get(node));
+        match (staged, self.store) {
+            (Some(branch), _) => Ok(branch.clone()),
+            (None, Some(store)) => store.get_branch(node),
+            (None, None) => Ok(None),
+        }
+    }
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<K, V, N>>> {
+        if let Some(change) = self.changes.leaves.get(leaf_hash) {
+            return Ok(change.get().cloned());
+        }
+        let staged = self.staged.and_then(|staged| staged.leaves.get(leaf_hash));
+        match (staged, self.store) {
+            (Some(leaf), _) => Ok(leaf.clone()),
+            (None, Some(store)) => store.get_leaf(leaf_hash),
+            (None, None) => Ok(None),
+        }
+    }
+    fn insert_branch(&mut self, node: H256, branch: BranchNode<K, N>) -> Result<()> {
+        self.changes.branches.insert(node, Change::Insert(branch));
+        Ok(())
+    }
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<K, V, N>) -> Result<()> {
+        self.changes.leaves.insert(leaf_hash, Change::Insert(leaf));
+        Ok(())
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<()> {
+        // only the removals of the backing nodes are staged
+        let in_store = match self.store {
+            Some(store) => store.get_branch(node)?.is_some(),
+            None => false,
+        };
+        let is_staged = self
+            .staged
+            .is_some_and(|staged| staged.branches.contains_key(node));
+        let branches = &mut self.changes.branches;
+        if in_store {
+            branches.insert(*node, Change::Remove);
+        } else if is_staged {
+            branches.insert(*node, Change::Unstage);
+        } else {
+            branches.remove(node);
+        }
+        Ok(())
+    }
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<()> {
+        let in_store = match self.store {
+            Some(store) => store.get_leaf(leaf_hash)?.is_some(),
+            None => false,
+        };
+        let is_staged = self
+            .staged
+            .is_some_and(|staged| staged.leaves.contains_key(leaf_hash));
+        let leaves = &mut self.changes.leaves;
+        if in_store {
+            leaves.insert(*leaf_hash, Change::Remove);
+        } else if is_staged {
+            leaves.insert(*leaf_hash, Change::Unstage);
+        } else {
+            leaves.remove(leaf_hash);
+        }
+        Ok(())
+    }
+
+    fn sorted_leaves<'b>(&'b self) -> impl Iterator<Item = (K, &'b V)>
+    where
+        V: 'b,
+    {
+        let store = self.store;
+        let changes = &self.changes.leaves;
+        // the staged leaves which the update doesn't change
+        let staged = self
+            .staged
+            .into_iter()
+            .flat_map(|staged| staged.leaves.iter())
+            .filter(move |(leaf_hash, _)| !changes.contains_key(*leaf_hash));
+        // keys of the backing leaves which are removed
+        let removed: Vec<K> = staged
+            .clone()
+            .filter(|(_, leaf)| leaf.is_none())
+            .map(|(leaf_hash, _)| leaf_hash)
+            .chain(
+                changes
+                    .iter()
+                    .filter(|(_, change)| matches!(change, Change::Remove))
+                    .map(|(leaf_hash, _)| leaf_hash),
+            )
+            .filter_map(|leaf_hash| store?.get_leaf(leaf_hash).ok().flatten())
+            .map(|leaf| leaf.key)
+            .collect();
+        let inserted = staged
+            .filter_map(|(_, leaf)| leaf.as_ref())
+            .chain(changes.values().filter_map(Change::get))
+            .map(|leaf| (leaf.key, &leaf.value));
+        store
+            .into_iter()
+            .flat_map(|store| store.sorted_leaves())
+            .filter(move |(key, _)| !removed.contains(key))
+            .chain(inserted)
+            .sorted_by_key(|(key, _)| *<K as Deref>::deref(key))
+    }
+
+    fn size(&self) -> usize {
+        self.sorted_leaves().count()
+    }
+}
+
+/// Updates staged over a tree, which is only changed by `commit`
+///
+/// The transaction computes the root of the staged updates and reads the
+/// staged tree. Dropping it without a commit rolls the updates back.
+#[derive(Debug)]
+pub struct Transaction<'a, H, K, V, S, const N: usize>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    tree: &'a mut SparseMerkleTree<H, K, V, S, N>,
+    root: H256,
+    staged: Staged<K, V, N>,
+}
+
+impl<'a, H, K, V, S, const N: usize> Transaction<'a, H, K, V, S, N>
+where
+    H: Hasher + Default,
+    K: Key<N>,
+    V: Value,
+    S: Store<K, V, N>,
+{
+    pub(crate) fn new(tree: &'a mut SparseMerkleTree<H, K, V, S, N>) -> Self {
+        let root = *tree.root();
+        Transaction {
+            tree,
+            root,
+            staged: Staged::default(),
+        }
+    }
+
+    /// Merkle root of the staged tree
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    /// Stage the update of a leaf, return the new staged root
+    /// set to zero value to delete a key
+    ///
+    /// The staged changes are left as they were if the update fails.
+    pub fn update(&mut self, key: K, value: V) -> Result<&H256> {
+        let mut tree = self.overlay();
+        tree.update(key, value)?;
+        let root = *tree.root();
+        let changes = tree.take_store().changes;
+        self.staged.apply(changes);
+        self.root = root;
+        Ok(&self.root)
+    }
+
+    /// Get value of a leaf in the staged tree
+    pub fn get(&self, key: &K) -> Result<V> {
+        self.overlay().get(key)
+    }
+
+    /// Generate merkle proof of the staged tree
+    pub fn merkle_proof(&self, keys: Vec<K>) -> Result<MerkleProof> {
+        self.overlay().merkle_proof(keys)
+    }
+
+    /// Write the staged changes to the backing store and move the tree to the
+    /// staged root
+    ///
+    /// An error of the backing store may leave it partially written.
+    pub fn commit(self) -> Result<()> {
+        let store = self.tree.store_mut();
+        for (node, branch) in self.staged.branches {
+            match branch {
+                Some(branch) => store.insert_branch(node, branch)?,
+                None => store.remove_branch(&node)?,
+            }
+        }
+        for (leaf_hash, leaf) in self.staged.leaves {
+            match leaf {
+                Some(leaf) => store.insert_leaf(leaf_hash, leaf)?,
+                None => store.remove_leaf(&leaf_hash)?,
+            }
+        }
+        self.tree.set_root(self.root);
+        Ok(())
+    }
+
+    /// Drop the staged changes, the tree is left unchanged
+    pub fn rollback(self) {}
+
+    fn overlay(&self) -> SparseMerkleTree<H, K, V, OverlayStore<'_, K, V, S, N>, N> {
+        let store = OverlayStore {
+            store: Some(self.tree.store()),
+            staged: Some(&self.staged),
+            changes: Changes::default(),
+        };
+        SparseMerkleTree::new(self.root, store)
+    }
+}
@@ -1,18 +1,28 @@
 use crate::{
     collections::{BTreeMap, VecDeque},
+    diff::Diff,
     error::{Error, Result},
+    iter::Iter,
     merge::{hash_leaf, merge},
-    merkle_proof::MerkleProof,
+    merkle_proof::{MerkleProof, NonMembershipProof, RangeProof, UpdateProof},
     proof_ics23,
+    snapshot::Snapshot,
     traits::{Hasher, Store, Value},
+    transaction::Transaction,
     vec::Vec,
     Key, InternalKey, EXPECTED_PATH_SIZE, H256,
 };
 #[cfg(feature = "borsh")]
 use borsh::{BorshDeserialize, BorshSerialize};
-use core::{cmp::max, marker::PhantomData};
+use core::{
+    cmp::max,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
 use ics23::commitment_proof::Proof;
-use ics23::{CommitmentProof, NonExistenceProof};
+use ics23::{
+    batch_entry, BatchEntry, BatchProof, CommitmentProof, ExistenceProof, NonExistenceProof,
+};
 
 /// A branch in the SMT
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -31,7 +41,12 @@ impl<K, const N: usize> BranchNode<K, N>
 where
     K: Key<N>,
 {
-    fn branch(&self, height: usize) -> (&H256, &H256) {
+    /// Check if the branch is the one built for a leaf, keyed by the leaf hash
+    pub(crate) fn is_leaf(&self) -> bool {
+        self.fork_height == 0 && self.sibling.is_zero()
+    }
+
+    pub(crate) fn branch(&self, height: usize) -> (&H256, &H256) {
         let is_right = self.key.get_bit(height);
         if is_right {
             (&self.sibling, &self.node)
@@ -94,6 +109,43 @@ where
         }
     }
 
+    /// Build a merkle tree from leaves sorted by key in ascending order
+    ///
+    /// The nodes are computed bottom-up in one pass and written into the store,
+    /// the result is identical to inserting the leaves with `update` in ascending
+    /// order. Zero values are skipped, return UnsortedKeys error if the keys are
+    /// not strictly ascending.
+    pub fn from_sorted_leaves<I>(leaves: I, store: S) -> Result<SparseMerkleTree<H, K, V, S, N>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut tree = Self::new(H256::zero(), store);
+        // subtrees waiting to be merged: (node, last key, fork height with the next leaf)
+        let mut stack: Vec<(H256, K, usize)> = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        let mut leaves = leaves.into_iter().filter(|(_k, v)| !v.is_zero()).peekable();
+        while let Some((key, value)) = leaves.next() {
+            let height = match leaves.peek() {
+                Some((next, _v)) if **next > *key => next.fork_height(&key),
+                Some(_) => return Err(Error::UnsortedKeys),
+                // the last leaf, merge everything left in the stack
+                None => usize::MAX,
+            };
+            let node = tree.store_leaf(key, value)?;
+            stack.push((node, key, height));
+            // merge neighbors which are closer to each other than to the next leaf
+            while stack.len() > 1 && stack[stack.len() - 2].2 < stack[stack.len() - 1].2 {
+                let (rhs, key, height) = stack.pop().unwrap();
+                let (lhs, _key, fork_height) = stack.pop().unwrap();
+                let parent = tree.merge_subtrees(fork_height, lhs, rhs, key)?;
+                stack.push((parent, key, height));
+            }
+        }
+        if let Some((root, _key, _height)) = stack.pop() {
+            tree.root = root;
+        }
+        Ok(tree)
+    }
+
     /// Merkle root
     pub fn root(&self) -> &H256 {
         &self.root
@@ -119,6 +171,16 @@ where
         &mut self.store
     }
 
+    /// Start a transaction staging updates over the tree, which is left
+    /// unchanged until the transaction commits
+    pub fn begin(&mut self) -> Transaction<'_, H, K, V, S, N> {
+        Transaction::new(self)
+    }
+
+    pub(crate) fn set_root(&mut self, root: H256) {
+        self.root = root;
+    }
+
     /// Update a leaf, return new merkle root
     /// set to zero value to delete a key
     pub fn update(&mut self, key: K, value: V) -> Result<&H256> {
@@ -178,22 +240,7 @@ where
         }
 
         // compute and store new leaf
-        let mut node = hash_leaf::<H, K, V, N>(&key, &value);
-        // notice when value is zero the leaf is deleted, so we do not need to store it
-        if !node.is_zero() {
-            self.store.insert_leaf(node, LeafNode { key, value })?;
-
-            // build at least one branch for leaf
-            self.store.insert_branch(
-                node,
-                BranchNode {
-                    key,
-                    fork_height: 0,
-                    node,
-                    sibling: H256::zero(),
-                },
-            )?;
-        }
+        let mut node = self.store_leaf(key, value)?;
 
         // recompute the tree from top to bottom
         while !path.is_empty() {
@@ -203,9 +250,9 @@ where
 
             let is_right = key.get_bit(height);
             let parent = if is_right {
-                merge::<H>(&sibling, &node)
+                merge::<H>(height, &sibling, &node)
             } else {
-                merge::<H>(&node, &sibling)
+                merge::<H>(height, &node, &sibling)
             };
 
             if !node.is_zero() {
@@ -224,10 +271,219 @@ where
         Ok(&self.root)
     }
 
+    /// Update multiple leaves at once, return new merkle root
+    /// set to zero value to delete a key
+    ///
+    /// The leaves are sorted by key, so the branches shared by several keys are
+    /// walked and recomputed only once. If a key appears more than once the last
+    /// value wins, the resulting root is the same as calling `update` in sequence.
+    pub fn update_all(&mut self, mut leaves: Vec<(K, V)>) -> Result<&H256> {
+        // sort leaves, the stable sort keeps duplicated keys in their given order
+        leaves.sort_by_key(|(k, _v)| **k);
+        // drop duplicated keys, keep the last value
+        leaves.dedup_by(|next, prev| {
+            if next.0 == prev.0 {
+                core::mem::swap(next, prev);
+                true
+            } else {
+                false
+            }
+        });
+        self.root = self.update_subtree(self.root, &leaves)?;
+        Ok(&self.root)
+    }
+
+    /// compute and store a new leaf, return the leaf hash
+    /// zero value leaves are not stored
+    fn store_leaf(&mut self, key: K, value: V) -> Result<H256> {
+        let node = hash_leaf::<H, K, V, N>(&key, &value);
+        // notice when value is zero the leaf is deleted, so we do not need to store it
+        if !node.is_zero() {
+            self.store.insert_leaf(node, LeafNode { key, value })?;
+
+            // build at least one branch for leaf
+            self.store.insert_branch(
+                node,
+                BranchNode {
+                    key,
+                    fork_height: 0,
+                    node,
+                    sibling: H256::zero(),
+                },
+            )?;
+        }
+        Ok(node)
+    }
+
+    /// apply sorted leaves to the subtree rooted at node, return the new subtree root
+    ///
+    /// The subtrees are walked with a stack of tasks instead of recursion, a
+    /// tree can be 8 * N branches deep.
+    fn update_subtree(&mut self, root: H256, leaves: &[(K, V)]) -> Result<H256> {
+        let mut tasks = vec![Task::Update(root, leaves)];
+        // the new roots of the subtrees, in the order they are computed
+        let mut nodes: Vec<H256> = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Update(node, []) => nodes.push(node),
+                Task::Update(node, leaves) => match self.store.get_branch(&node)? {
+                    Some(branch) => {
+                        self.update_branch(node, branch, leaves, &mut tasks, &mut nodes)?
+                    }
+                    // the descendants are zeros
+                    None => tasks.push(Task::Build(leaves)),
+                },
+                Task::UpdateBranch(node, _branch, []) => nodes.push(node),
+                Task::UpdateBranch(node, branch, leaves) => {
+                    self.update_branch(node, branch, leaves, &mut tasks, &mut nodes)?
+                }
+                Task::Build(leaves) => self.build_subtree(leaves, &mut tasks, &mut nodes)?,
+                Task::Merge { height, key, swap } => {
+                    let second = nodes.pop().unwrap();
+                    let first = nodes.pop().unwrap();
+                    let (lhs, rhs) = if swap {
+                        (second, first)
+                    } else {
+                        (first, second)
+                    };
+                    nodes.push(self.merge_subtrees(height, lhs, rhs, key)?);
+                }
+            }
+        }
+        Ok(nodes.pop().unwrap_or(root))
+    }
+
+    /// push the tasks applying the sorted leaves to the subtree of branch
+    fn update_branch<'a>(
+        &mut self,
+        node: H256,
+        branch: BranchNode<K, N>,
+        leaves: &'a [(K, V)],
+        tasks: &mut Vec<Task<'a, K, V, N>>,
+        nodes: &mut Vec<H256>,
+    ) -> Result<()> {
+        let is_leaf = branch.is_leaf();
+        // all keys of the subtree share the bits from this height
+        let shared_height = if is_leaf { 0 } else { branch.fork_height + 1 };
+        // leaves are sorted, so the highest fork with the subtree is found at either end
+        let fork_with = |k: &K| {
+            if **k == *branch.key {
+                None
+            } else {
+                Some(k.fork_height(&branch.key))
+            }
+        };
+        let first_key = leaves[0].0;
+        let last_key = leaves[leaves.len() - 1].0;
+        let fork_height = max(fork_with(&first_key), fork_with(&last_key))
+            .filter(|height| *height >= shared_height);
+
+        match fork_height {
+            Some(height) => {
+                // some keys are outside of the subtree, so a new branch forks above it
+                let (left, right) = leaves.split_at(right_side_index(leaves, height));
+                let is_right = branch.key.get_bit(height);
+                let (inside, outside) = if is_right {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                // the subtree is updated first, then the outside is built
+                tasks.push(Task::Merge {
+                    height,
+                    key: last_key,
+                    swap: is_right,
+                });
+                tasks.push(Task::Build(outside));
+                tasks.push(Task::UpdateBranch(node, branch, inside));
+            }
+            None if is_leaf => {
+                // the leaf itself is updated, duplicated keys are already removed
+                debug_assert_eq!(leaves.len(), 1);
+                self.store.remove_leaf(&node)?;
+                self.store.remove_branch(&node)?;
+                let (key, value) = leaves[0].clone();
+                nodes.push(self.store_leaf(key, value)?);
+            }
+            None => {
+                // all keys are inside of the subtree, recompute both children
+                let height = branch.fork_height;
+                let (left_leaves, right_leaves) = leaves.split_at(right_side_index(leaves, height));
+                let (left, right) = branch.branch(height);
+                let (left, right) = (*left, *right);
+                self.store.remove_branch(&node)?;
+                tasks.push(Task::Merge {
+                    height,
+                    key: last_key,
+                    swap: false,
+                });
+                tasks.push(Task::Update(right, right_leaves));
+                tasks.push(Task::Update(left, left_leaves));
+            }
+        }
+        Ok(())
+    }
+
+    /// push the tasks building a subtree from sorted leaves which are not in
+    /// the tree yet, a single leaf is stored at once
+    fn build_subtree<'a>(
+        &mut self,
+        leaves: &'a [(K, V)],
+        tasks: &mut Vec<Task<'a, K, V, N>>,
+        nodes: &mut Vec<H256>,
+    ) -> Result<()> {
+        match leaves {
+            [] => nodes.push(H256::zero()),
+            [(key, value)] => nodes.push(self.store_leaf(*key, value.clone())?),
+            [(first, _), .., (last, _)] => {
+                let height = first.fork_height(last);
+                let (left, right) = leaves.split_at(right_side_index(leaves, height));
+                tasks.push(Task::Merge {
+                    height,
+                    key: *last,
+                    swap: false,
+                });
+                tasks.push(Task::Build(right));
+                tasks.push(Task::Build(left));
+            }
+        }
+        Ok(())
+    }
+
+    /// merge two subtrees at height, store the branch if both sides are non-zero
+    fn merge_subtrees(&mut self, height: usize, lhs: H256, rhs: H256, key: K) -> Result<H256> {
+        let parent = merge::<H>(height, &lhs, &rhs);
+        // when one side is zero the parent is the other side, which is already stored
+        if !lhs.is_zero() && !rhs.is_zero() {
+            let (node, sibling) = if key.get_bit(height) {
+                (rhs, lhs)
+            } else {
+                (lhs, rhs)
+            };
+            self.store.insert_branch(
+                parent,
+                BranchNode {
+                    fork_height: height,
+                    key,
+                    node,
+                    sibling,
+                },
+            )?;
+        }
+        Ok(parent)
+    }
+
     /// Get value of a leaf
     /// return zero value if leaf not exists
     pub fn get(&self, key: &K) -> Result<V> {
-        let mut node = self.root;
+        self.get_at(&self.root, key)
+    }
+
+    /// Get value of a leaf in the tree with root, which nodes must still be in
+    /// the store, see `versioned_store::VersionedStore`
+    /// return zero value if leaf not exists
+    pub fn get_at(&self, root: &H256, key: &K) -> Result<V> {
+        let mut node = *root;
         // children must equal zero when parent equals zero
         while !node.is_zero() {
             let branch_node = match self.store.get_branch(&node)? {
@@ -255,14 +511,57 @@ where
         }
     }
 
+    /// Iterate all leaves in ascending key order
+    pub fn iter(&self) -> Iter<'_, K, V, S, N> {
+        self.range(..)
+    }
+
+    /// Iterate the leaves which keys are in the range, in ascending key order
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V, S, N> {
+        let bound = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(**k),
+            Bound::Excluded(k) => Bound::Excluded(**k),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Iter::new(
+            &self.store,
+            &self.root,
+            bound(range.start_bound()),
+            bound(range.end_bound()),
+        )
+    }
+
+    /// Iterate the leaves which internal keys start with the prefix bytes,
+    /// in ascending key order
+    pub fn prefix(&self, prefix: &[u8]) -> Iter<'_, K, V, S, N> {
+        Iter::with_prefix(&self.store, &self.root, prefix)
+    }
+
+    /// Iterate the changes of the leaves from the tree with the old root to
+    /// the one with the new root, in ascending key order
+    ///
+    /// The nodes of both trees must be in the store, see
+    /// `versioned_store::VersionedStore`
+    pub fn diff(&self, old_root: &H256, new_root: &H256) -> Diff<'_, K, V, S, N> {
+        Diff::new(&self.store, old_root, new_root)
+    }
+
+    /// Split the leaves of the tree with root in chunks of chunk_size leaves,
+    /// each with a merkle proof of its boundaries, see
+    /// `snapshot::SnapshotRestorer`
+    pub fn snapshot(&self, root: &H256, chunk_size: usize) -> Snapshot<'_, H, K, V, S, N> {
+        Snapshot::new(self, root, chunk_size)
+    }
+
     /// fetch merkle path of key into cache
     /// cache: (height, key) -> node
     fn fetch_merkle_path(
         &self,
+        root: &H256,
         key: &K,
         cache: &mut BTreeMap<(usize, InternalKey<N>), H256>,
     ) -> Result<()> {
-        let mut node = self.root;
+        let mut node = *root;
         let mut height = self
             .store
             .get_branch(&node)?
@@ -312,7 +611,7 @@ where
                         // mark sibling's index, sibling on the right path.
                         sibling_key.set_bit(height);
                     };
-                    cache.insert((height as usize, sibling_key), sibling);
+                    cache.insert((height, sibling_key), sibling);
                     if let Some(branch_node) = self.store.get_branch(&node)? {
                         let fork_height =
                             max(key.fork_height(&branch_node.key), branch_node.fork_height);
@@ -326,7 +625,13 @@ where
     }
 
     /// Generate merkle proof
-    pub fn merkle_proof(&self, mut keys: Vec<K>) -> Result<MerkleProof> {
+    pub fn merkle_proof(&self, keys: Vec<K>) -> Result<MerkleProof> {
+        self.merkle_proof_at(&self.root, keys)
+    }
+
+    /// Generate merkle proof in the tree with root, which nodes must still be
+    /// in the store, see `versioned_store::VersionedStore`
+    pub fn merkle_proof_at(&self, root: &H256, mut keys: Vec<K>) -> Result<MerkleProof> {
         if keys.is_empty() {
             return Err(Error::EmptyKeys);
         }
@@ -337,7 +642,7 @@ where
         // fetch all merkle path
         let mut cache: BTreeMap<(usize, _), H256> = Default::default();
         for k in &keys {
-            self.fetch_merkle_path(k, &mut cache)?;
+            self.fetch_merkle_path(root, k, &mut cache)?;
         }
 
         // (node, height)
@@ -412,15 +717,88 @@ where
         Ok(MerkleProof::new(leaves_path, proof))
     }
 
+    /// Generate merkle proof for the non-existing key
+    pub fn non_membership_merkle_proof(&self, key: &K) -> Result<NonMembershipProof<K, V>> {
+        let value = self.get(key)?;
+        if value != V::zero() {
+            return Err(Error::NonExistenceProof);
+        }
+        let (left, right) = self.neighbors(key)?;
+        let left = left.map(|leaf| (leaf.key, leaf.value));
+        let right = right.map(|leaf| (leaf.key, leaf.value));
+        let keys: Vec<K> = left.iter().chain(right.iter()).map(|(k, _v)| *k).collect();
+        let merkle_proof = if keys.is_empty() {
+            MerkleProof::new(Vec::new(), Vec::new())
+        } else {
+            self.merkle_proof(keys)?
+        };
+        Ok(NonMembershipProof::new(left, right, merkle_proof))
+    }
+
+    /// Generate merkle proof that the leaves which keys are in [start, end)
+    /// are all the leaves of the range
+    ///
+    /// return UnsortedKeys error if end is before start
+    pub fn range_proof(&self, start: &K, end: &K) -> Result<RangeProof<K, V>> {
+        if **end < **start {
+            return Err(Error::UnsortedKeys);
+        }
+        let leaves = self.range(*start..*end).collect::<Result<Vec<_>>>()?;
+        let (left, _) = self.neighbors(start)?;
+        let value = self.get(end)?;
+        let right = if value.is_zero() {
+            self.neighbors(end)?.1.map(|leaf| (leaf.key, leaf.value))
+        } else {
+            Some((*end, value))
+        };
+        let left = left.map(|leaf| (leaf.key, leaf.value));
+        let keys: Vec<K> = left
+            .iter()
+            .chain(leaves.iter())
+            .chain(right.iter())
+            .map(|(k, _v)| *k)
+            .collect();
+        let merkle_proof = if keys.is_empty() {
+            MerkleProof::new(Vec::new(), Vec::new())
+        } else {
+            self.merkle_proof(keys)?
+        };
+        Ok(RangeProof::new(left, leaves, right, merkle_proof))
+    }
+
+    /// Generate merkle proof of the leaves the writes touch, before they are
+    /// applied, to verify the transition to the root after the writes
+    ///
+    /// The leaves next to the missing keys are opened too, to show the keys
+    /// are missing.
+    pub fn update_proof(&self, writes: &[(K, V)]) -> Result<UpdateProof<K, V>> {
+        if writes.is_empty() {
+            return Err(Error::EmptyKeys);
+        }
+        let mut keys: Vec<K> = writes.iter().map(|(k, _v)| *k).collect();
+        for (key, _value) in writes {
+            if self.get(key)?.is_zero() {
+                let (left, right) = self.neighbors(key)?;
+                keys.extend(left.iter().chain(right.iter()).map(|leaf| leaf.key));
+            }
+        }
+        keys.sort_unstable_by_key(|k| **k);
+        keys.dedup_by_key(|k| **k);
+        let leaves = keys
+            .iter()
+            .map(|k| Ok((*k, self.get(k)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let merkle_proof = self.merkle_proof(keys)?;
+        Ok(UpdateProof::new(leaves, merkle_proof))
+    }
+
     /// Generate ICS 23 commitment proof for the existing key
     pub fn membership_proof(&self, key: &K) -> Result<CommitmentProof> {
         let value = self.get(key)?;
         if value == V::zero() {
             return Err(Error::ExistenceProof);
         }
-        let merkle_proof = self.merkle_proof(vec![*key])?;
-        let existence_proof =
-            proof_ics23::convert(merkle_proof, key, &value, H::hash_op())?;
+        let existence_proof = self.existence_proof(key, &value)?;
         Ok(CommitmentProof {
             proof: Some(Proof::Exist(existence_proof)),
         })
@@ -433,9 +811,102 @@ where
             return Err(Error::NonExistenceProof);
         }
 
+        let proof = self.non_existence_proof(key, &mut BTreeMap::new())?;
+        Ok(CommitmentProof {
+            proof: Some(Proof::Nonexist(proof)),
+        })
+    }
+
+    /// Generate ICS 23 batch proof for the existing and non-existing keys
+    ///
+    /// The existence proof of a leaf is generated once, even when it is the
+    /// neighbor of several non-existing keys
+    pub fn batch_proof(&self, keys: &[K]) -> Result<CommitmentProof> {
+        if keys.is_empty() {
+            return Err(Error::EmptyKeys);
+        }
+        let mut keys = keys.to_vec();
+        keys.sort_unstable_by_key(|k| **k);
+        keys.dedup();
+
+        let mut existence_proofs = BTreeMap::new();
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let value = self.get(key)?;
+            let proof = if value.is_zero() {
+                batch_entry::Proof::Nonexist(self.non_existence_proof(key, &mut existence_proofs)?)
+            } else {
+                batch_entry::Proof::Exist(self.cached_existence_proof(
+                    key,
+                    &value,
+                    &mut existence_proofs,
+                )?)
+            };
+            entries.push(BatchEntry { proof: Some(proof) });
+        }
+        Ok(CommitmentProof {
+            proof: Some(Proof::Batch(BatchProof { entries })),
+        })
+    }
+
+    /// Generate ICS 23 compressed batch proof for the existing and non-existing
+    /// keys, the inner ops shared by the entries are only stored once
+    pub fn compressed_batch_proof(&self, keys: &[K]) -> Result<CommitmentProof> {
+        let batch_proof = self.batch_proof(keys)?;
+        ics23::compress(&batch_proof).map_err(|_| Error::CorruptedProof)
+    }
+
+    /// Generate ICS 23 existence proof for the existing key and its value
+    fn existence_proof(&self, key: &K, value: &V) -> Result<ExistenceProof> {
+        let merkle_proof = self.merkle_proof(vec![*key])?;
+        proof_ics23::convert::<H, K, V, N>(merkle_proof, key, value)
+    }
+
+    /// Get the existence proof of the key from the already generated proofs or
+    /// generate it
+    fn cached_existence_proof(
+        &self,
+        key: &K,
+        value: &V,
+        existence_proofs: &mut BTreeMap<InternalKey<N>, ExistenceProof>,
+    ) -> Result<ExistenceProof> {
+        if let Some(existence_proof) = existence_proofs.get(&**key) {
+            return Ok(existence_proof.clone());
+        }
+        let existence_proof = self.existence_proof(key, value)?;
+        existence_proofs.insert(**key, existence_proof.clone());
+        Ok(existence_proof)
+    }
+
+    /// Generate ICS 23 non-existence proof for the non-existing key, reusing
+    /// the existence proofs of the neighbors which have already been generated
+    fn non_existence_proof(
+        &self,
+        key: &K,
+        existence_proofs: &mut BTreeMap<InternalKey<N>, ExistenceProof>,
+    ) -> Result<NonExistenceProof> {
+        proof_ics23::prehash_key_op::<K, N>()?;
+        let (left, right) = self.neighbors(key)?;
+        let left = left
+            .map(|leaf| self.cached_existence_proof(&leaf.key, &leaf.value, existence_proofs))
+            .transpose()?;
+        let right = right
+            .map(|leaf| self.cached_existence_proof(&leaf.key, &leaf.value, existence_proofs))
+            .transpose()?;
+        Ok(NonExistenceProof {
+            key: key.to_vec(),
+            left,
+            right,
+        })
+    }
+
+    /// Get the leaves next to the key, the greatest smaller key
+    /// and the least greater key
+    #[allow(clippy::type_complexity)]
+    fn neighbors(&self, key: &K) -> Result<(Option<LeafNode<K, V, N>>, Option<LeafNode<K, V, N>>)> {
         // fetch all merkle path
         let mut cache: BTreeMap<(usize, _), H256> = Default::default();
-        self.fetch_merkle_path(key, &mut cache)?;
+        self.fetch_merkle_path(&self.root, key, &mut cache)?;
         let mut left = None;
         let mut right = None;
         for (_, node) in cache.iter() {
@@ -447,61 +918,32 @@ where
             let is_right = key.get_bit(fork_height);
             if is_right && left.is_none() {
                 // get the left which is the most right in the left subtree
-                let mut n = *node;
-                while let Some(branch) = self.store.get_branch(&n)? {
-                    if branch.fork_height == 0 {
-                        break;
-                    }
-                    let (left_node, right_node) = branch.branch(branch.fork_height);
-                    n = if right_node.is_zero() {
-                        *left_node
-                    } else {
-                        *right_node
-                    };
-                }
-                let leaf = self.store.get_leaf(&n)?.expect("the leaf should exist");
-                let merkle_proof = self.merkle_proof(vec![leaf.key])?;
-                left = Some(proof_ics23::convert(
-                    merkle_proof,
-                    &leaf.key,
-                    &leaf.value,
-                    H::hash_op(),
-                )?);
+                left = Some(self.edge_leaf(*node, true)?);
             } else if !is_right && right.is_none() {
                 // get the right which is the most left in the right subtree
-                let mut n = *node;
-                while let Some(branch) = self.store.get_branch(&n)? {
-                    if branch.fork_height == 0 {
-                        break;
-                    }
-                    let (left_node, right_node) = branch.branch(branch.fork_height);
-                    n = if left_node.is_zero() {
-                        *right_node
-                    } else {
-                        *left_node
-                    };
-                }
-                let leaf = self.store.get_leaf(&n)?.expect("the leaf should exist");
-                let merkle_proof = self.merkle_proof(vec![leaf.key])?;
-                right = Some(proof_ics23::convert(
-                    merkle_proof,
-                    &leaf.key,
-                    &leaf.value,
-                    H::hash_op(),
-                )?);
+                right = Some(self.edge_leaf(*node, false)?);
             }
             if left.is_some() && right.is_some() {
                 break;
             }
         }
-        let proof = NonExistenceProof {
-            key: key.to_vec(),
-            left,
-            right,
-        };
-        Ok(CommitmentProof {
-            proof: Some(Proof::Nonexist(proof)),
-        })
+        Ok((left, right))
+    }
+
+    /// Get the most right or the most left leaf of the subtree
+    fn edge_leaf(&self, mut node: H256, most_right: bool) -> Result<LeafNode<K, V, N>> {
+        while let Some(branch) = self.store.get_branch(&node)? {
+            if branch.is_leaf() {
+                break;
+            }
+            let (left_node, right_node) = branch.branch(branch.fork_height);
+            node = if (most_right && !right_node.is_zero()) || left_node.is_zero() {
+                *right_node
+            } else {
+                *left_node
+            };
+        }
+        Ok(self.store.get_leaf(&node)?.expect("the leaf should exist"))
     }
 
     /// Recompute the root of the merkle tree from the store. Check if it agrees with the
@@ -524,14 +966,10 @@ where
         let mut leaves = Vec::with_capacity(self.store.size());
         for ((k1, v1), (k2, _)) in pairs {
             let height = k1.fork_height(&k2);
-            let hash = hash_leaf::<H, K, V, N>(&k1, &v1);
+            let hash = hash_leaf::<H, K, V, N>(&k1, v1);
             leaves.push((hash, height));
         }
-        let (last_k, last_v) = self.store
-            .sorted_leaves()
-            .last()
-            .map(|(k, v)| (k, v))
-            .unwrap();
+        let (last_k, last_v) = self.store.sorted_leaves().last().unwrap();
         let last = hash_leaf::<H, K, V, N>(&last_k, last_v);
         if leaves.is_empty() {
             return self.root == last;
@@ -553,7 +991,11 @@ where
             // find the next pair of nodes to merge
             let next_left = find_next(&leaves);
             let next_right = next_left + 1;
-            let merged = merge::<H>(&leaves[next_left].0, &leaves[next_right].0);
+            let merged = merge::<H>(
+                leaves[next_left].1,
+                &leaves[next_left].0,
+                &leaves[next_right].0,
+            );
             // perform the merge
             let (_, dist) = leaves.remove(next_right);
             leaves[next_left].0 = merged;
@@ -568,3 +1010,29 @@ where
         leaves[0].0 == self.root
     }
 }
+
+/// Pending step of `SparseMerkleTree::update_all`, each one leaves the root of
+/// a subtree on the stack of nodes
+enum Task<'a, K, V, const N: usize>
+where
+    K: Key<N>,
+{
+    /// apply the leaves to the subtree rooted at the node
+    Update(H256, &'a [(K, V)]),
+    /// apply the leaves to the subtree of the branch stored at the node
+    UpdateBranch(H256, BranchNode<K, N>, &'a [(K, V)]),
+    /// build a subtree from leaves which are not in the tree yet
+    Build(&'a [(K, V)]),
+    /// merge the last two subtrees at height, swapped if the first one
+    /// computed is the right side
+    Merge { height: usize, key: K, swap: bool },
+}
+
+/// index of the first leaf on the right side of height
+/// the leaves should be sorted and share the bits above height
+fn right_side_index<K, V, const N: usize>(leaves: &[(K, V)], height: usize) -> usize
+where
+    K: Key<N>,
+{
+    leaves.partition_point(|(k, _v)| !k.get_bit(height))
+}
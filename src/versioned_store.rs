@@ -0,0 +1,287 @@
+use crate::{
+    collections::{BTreeMap, BTreeSet},
+    error::{Error, Result},
+    string::ToString,
+    traits::{IterableStore, Store},
+    tree::{BranchNode, LeafNode},
+    vec::Vec,
+    Key, H256,
+};
+
+/// Store which keeps the nodes the tree removes on update
+///
+/// The nodes are content addressed, so every root the tree had can still be
+/// read with `SparseMerkleTree::get_at` and `merkle_proof_at`. The leaves of
+/// all the versions are counted by `sorted_leaves` and `size`, so the tree
+/// can't be validated against the store.
+///
+/// Each `commit` records the root of a version with the nodes it introduced
+/// and retired, so `prune` can delete the nodes only the dropped versions
+/// reference without walking the trees.
+#[derive(Debug, Default, Clone)]
+pub struct VersionedStore<S> {
+    store: S,
+    // the version of the next commit
+    version: u64,
+    // committed roots by version
+    roots: BTreeMap<u64, H256>,
+    // version which introduced each node of the current tree, for the nodes
+    // inserted since the tracking started
+    introduced: BTreeMap<Node, u64>,
+    // nodes removed from the tree, in the order of removal
+    retired: Vec<Retired>,
+    // whether the store changed since the last commit
+    dirty: bool,
+}
+
+/// A node of the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    Branch(H256),
+    Leaf(H256),
+}
+
+/// A node removed from the tree, referenced by the versions from
+/// `introduced`, or the first tracked one if None, until `retired` excluded
+#[derive(Debug, Clone)]
+struct Retired {
+    node: Node,
+    introduced: Option<u64>,
+    retired: u64,
+}
+
+impl Retired {
+    fn is_referenced(&self, versions: &BTreeSet<u64>) -> bool {
+        versions
+            .range(self.introduced.unwrap_or(0)..self.retired)
+            .next()
+            .is_some()
+    }
+}
+
+impl<S> VersionedStore<S> {
+    pub fn new(store: S) -> Self {
+        VersionedStore {
+            store,
+            version: 0,
+            roots: BTreeMap::new(),
+            introduced: BTreeMap::new(),
+            retired: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// return the inner store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// return the mutable inner store
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Destruct the structure into the inner store
+    pub fn take_store(self) -> S {
+        self.store
+    }
+
+    /// Record the changes since the last commit as a version of the tree
+    /// with the root they lead to
+    pub fn commit(&mut self, root: &H256) {
+        self.roots.insert(self.version, *root);
+        self.version += 1;
+        self.dirty = false;
+    }
+
+    /// return the committed roots which weren't pruned, by version
+    pub fn roots(&self) -> &BTreeMap<u64, H256> {
+        &self.roots
+    }
+
+    /// Check if the store changed since the last commit
+    pub fn has_pending(&self) -> bool {
+        self.dirty
+    }
+
+    fn track_insert(&mut self, node: Node) {
+        self.dirty = true;
+        self.introduced.entry(node).or_insert(self.version);
+    }
+
+    fn track_remove(&mut self, node: Node) {
+        self.dirty = true;
+        let introduced = self.introduced.remove(&node);
+        self.retired.push(Retired {
+            node,
+            introduced,
+            retired: self.version,
+        });
+    }
+
+    /// Delete the nodes which are only referenced by the versions whose roots
+    /// aren't kept, and forget those versions
+    ///
+    /// The nodes of the last commit are never deleted. return the number of
+    /// deleted nodes
+    pub fn prune<K, V, const N: usize>(&mut self, keep_roots: &[H256]) -> Result<usize>
+    where
+        K: Key<N>,
+        S: Store<K, V, N>,
+    {
+        if self.dirty {
+            return Err(Error::Store("Can't prune uncommitted changes".to_string()));
+        }
+        let kept: BTreeSet<u64> = self
+            .roots
+            .iter()
+            .filter(|(_, root)| keep_roots.contains(root))
+            .map(|(version, _)| *version)
+            .collect();
+        // a node may have been retired several times
+        let mut referenced: BTreeMap<Node, bool> = BTreeMap::new();
+        for retired in &self.retired {
+            let is_referenced =
+                retired.is_referenced(&kept) || self.introduced.contains_key(&retired.node);
+            *referenced.entry(retired.node).or_insert(false) |= is_referenced;
+        }
+        let mut deleted = 0;
+        for (node, _) in referenced.iter().filter(|(_, referenced)| !**referenced) {
+            match node {
+                Node::Branch(node) => self.store.remove_branch(node)?,
+                Node::Leaf(leaf_hash) => self.store.remove_leaf(leaf_hash)?,
+            }
+            deleted += 1;
+        }
+        self.retired
+            .retain(|retired| referenced.get(&retired.node) == Some(&true));
+        self.roots.retain(|version, _| kept.contains(version));
+        Ok(deleted)
+    }
+
+    /// Delete the nodes which aren't reachable from the live roots with `gc`
+    ///
+    /// The versions whose roots aren't live are forgotten. return the number
+    /// of deleted nodes
+    pub fn gc<K, V, const N: usize>(&mut self, live_roots: &[H256]) -> Result<usize>
+    where
+        K: Key<N>,
+        S: IterableStore<K, V, N>,
+    {
+        if self.dirty {
+            return Err(Error::Store(
+                "Can't collect uncommitted changes".to_string(),
+            ));
+        }
+        let deleted = gc(&mut self.store, live_roots)?;
+        // forget the deleted nodes
+        let store = &self.store;
+        let exists = |node: &Node| match node {
+            Node::Branch(node) => store.get_branch(node).map(|b| b.is_some()),
+            Node::Leaf(leaf_hash) => store.get_leaf(leaf_hash).map(|l| l.is_some()),
+        };
+        let mut introduced = BTreeMap::new();
+        for (node, version) in &self.introduced {
+            if exists(node)? {
+                introduced.insert(*node, *version);
+            }
+        }
+        let mut retired = Vec::new();
+        for entry in &self.retired {
+            if exists(&entry.node)? {
+                retired.push(entry.clone());
+            }
+        }
+        self.introduced = introduced;
+        self.retired = retired;
+        self.roots.retain(|_, root| live_roots.contains(root));
+        Ok(deleted)
+    }
+}
+
+impl<K, V, S, const N: usize> Store<K, V, N> for VersionedStore<S>
+where
+    K: Key<N>,
+    S: Store<K, V, N>,
+{
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode<K, N>>> {
+        self.store.get_branch(node)
+    }
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<K, V, N>>> {
+        self.store.get_leaf(leaf_hash)
+    }
+    fn insert_branch(&mut self, node: H256, branch: BranchNode<K, N>) -> Result<()> {
+        self.track_insert(Node::Branch(node));
+        self.store.insert_branch(node, branch)
+    }
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<K, V, N>) -> Result<()> {
+        self.track_insert(Node::Leaf(leaf_hash));
+        self.store.insert_leaf(leaf_hash, leaf)
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<()> {
+        // older roots may still reference the branch
+        self.track_remove(Node::Branch(*node));
+        Ok(())
+    }
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<()> {
+        // older roots may still reference the leaf
+        self.track_remove(Node::Leaf(*leaf_hash));
+        Ok(())
+    }
+
+    fn sorted_leaves<'a>(&'a self) -> impl Iterator<Item = (K, &'a V)>
+    where
+        V: 'a,
+    {
+        self.store.sorted_leaves()
+    }
+
+    fn size(&self) -> usize {
+        self.store.size()
+    }
+}
+
+/// Mark the nodes reachable from the live roots and sweep the other nodes of
+/// the store, which may have been written without tracking the versions
+///
+/// return the number of deleted nodes
+pub fn gc<K, V, S, const N: usize>(store: &mut S, live_roots: &[H256]) -> Result<usize>
+where
+    K: Key<N>,
+    S: IterableStore<K, V, N>,
+{
+    // a leaf is stored under the hash of its branch
+    let mut reachable = BTreeSet::new();
+    let mut stack: Vec<H256> = live_roots
+        .iter()
+        .filter(|root| !root.is_zero())
+        .copied()
+        .collect();
+    while let Some(node) = stack.pop() {
+        if !reachable.insert(node) {
+            continue;
+        }
+        if let Some(branch) = store.get_branch(&node)? {
+            if !branch.is_leaf() {
+                stack.push(branch.node);
+                stack.push(branch.sibling);
+            }
+        }
+    }
+
+    let branches: Vec<H256> = store
+        .branch_hashes()
+        .filter(|node| !reachable.contains(node))
+        .collect();
+    let leaves: Vec<H256> = store
+        .leaf_hashes()
+        .filter(|leaf_hash| !reachable.contains(leaf_hash))
+        .collect();
+    for node in &branches {
+        store.remove_branch(node)?;
+    }
+    for leaf_hash in &leaves {
+        store.remove_leaf(leaf_hash)?;
+    }
+    Ok(branches.len() + leaves.len())
+}
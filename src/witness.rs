@@ -0,0 +1,119 @@
+use crate::{
+    error::Result,
+    merge::{hash_leaf, merge},
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Value},
+    vec::Vec,
+    Key, H256,
+};
+
+/// Paths of the proven leaves to the root in fixed depth arrays, for circuits
+/// which can't skip the zero siblings of a `MerkleProof`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Witness<K, V> {
+    root: H256,
+    leaves: Vec<LeafWitness<K, V>>,
+}
+
+impl<K, V> Witness<K, V> {
+    /// Expand the merkle proof of the leaves into a path of 8 * N heights for
+    /// each leaf
+    ///
+    /// return the errors of `MerkleProof::compute_root`
+    pub fn new<H, const N: usize>(
+        merkle_proof: MerkleProof,
+        mut leaves: Vec<(K, V)>,
+    ) -> Result<Self>
+    where
+        H: Hasher + Default,
+        K: Key<N>,
+        V: Value,
+    {
+        leaves.sort_unstable_by_key(|(k, _v)| **k);
+        let mut siblings = vec![vec![H256::zero(); 8 * N]; leaves.len()];
+        let root = merkle_proof.compute_root_with::<H, K, V, _, N>(
+            leaves.clone(),
+            |height, range, sibling| {
+                for path in &mut siblings[range] {
+                    path[height] = *sibling;
+                }
+            },
+        )?;
+        let leaves = leaves
+            .into_iter()
+            .zip(siblings)
+            .map(|((key, value), siblings)| LeafWitness {
+                leaf_hash: hash_leaf::<H, K, V, N>(&key, &value),
+                directions: (0..8 * N).map(|height| key.get_bit(height)).collect(),
+                key,
+                value,
+                siblings,
+            })
+            .collect();
+        Ok(Witness { root, leaves })
+    }
+
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    /// return the paths of the leaves in ascending key order
+    pub fn leaves(&self) -> &[LeafWitness<K, V>] {
+        &self.leaves
+    }
+}
+
+/// Path of a leaf to the root
+///
+/// The sibling and the direction at index h are the sibling of the node at
+/// height h and whether the node is its right child. A zero side leaves the
+/// other one unchanged as in `merge::merge`, so the parent is the node if the
+/// sibling is zero and the hash of both ordered by the direction otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafWitness<K, V> {
+    key: K,
+    value: V,
+    leaf_hash: H256,
+    siblings: Vec<H256>,
+    directions: Vec<bool>,
+}
+
+impl<K, V> LeafWitness<K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn leaf_hash(&self) -> &H256 {
+        &self.leaf_hash
+    }
+
+    /// return the siblings from height 0 to the root, zero where a height has
+    /// no sibling
+    pub fn siblings(&self) -> &[H256] {
+        &self.siblings
+    }
+
+    /// return the bits of the key from height 0, true if the node is a right
+    /// child
+    pub fn directions(&self) -> &[bool] {
+        &self.directions
+    }
+
+    /// Compute the root from the fixed depth path, the way a circuit does
+    pub fn compute_root<H: Hasher + Default>(&self) -> H256 {
+        let mut node = self.leaf_hash;
+        let path = self.siblings.iter().zip(&self.directions);
+        for (height, (sibling, is_right)) in path.enumerate() {
+            node = if *is_right {
+                merge::<H>(height, sibling, &node)
+            } else {
+                merge::<H>(height, &node, sibling)
+            };
+        }
+        node
+    }
+}